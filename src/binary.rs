@@ -0,0 +1,492 @@
+//! Binary file format of the SBF data structure
+//!
+//! A compact and versioned alternative to the `serde_support` serialization, readable across
+//! crate versions.
+//! All the integers are little endian, the layout of version 2 is:
+//!
+//! | Field             | Encoding                                                             |
+//! |-------------------|----------------------------------------------------------------------|
+//! | magic             | the 4 bytes `SBF\0`                                                  |
+//! | version           | `u16`                                                                |
+//! | endianness        | `u8`, `1` for little endian                                          |
+//! | hash function     | `u8`, see below                                                      |
+//! | index mode        | `u8`, `0` salted, `1` double hashing, `2` libSBF, `3` blocked        |
+//! | input mode        | `u8`, `0` xor, `1` streamed, `2` libSBF                              |
+//! | cell width        | `u8`, number of bytes of each cell                                   |
+//! | cells             | `u64`, number of cells                                               |
+//! | area number       | `u64`, number of areas including the empty area `0`                  |
+//! | salts             | `u8` kind, then the stored salts or the master key                   |
+//! | metrics           | `u8` flag, then the counters of the metrics if the flag is set       |
+//! | padding           | zero bytes up to a multiple of 64 bytes from the start of the file   |
+//! | cells             | each cell as an unsigned integer of the cell width                   |
+//! | checksum          | `u32`, CRC-32 of all the previous bytes                              |
+//!
+//! The hash functions are identified as MD5 `1`, MD4 `2`, SHA-256 `3`, BLAKE3 `4`, xxHash3 `5`,
+//! SipHash `6` and SHA-1 `7`.
+//! Stored salts are written as the `u64` number of salts followed by each salt as its `u64`
+//! length and its bytes, a master key as its 32 bytes followed by the `u64` number of salts and
+//! the `u64` length of each salt.
+//! The metrics counters are the `u64` members and collisions, followed by the `u64` number of
+//! areas and the `u64` members and self collisions of each area.
+//! The padding keeps the cells of memory-mapped filters aligned to the cache lines, it is missing
+//! in the files of version 1.
+
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use crc32fast::Hasher;
+
+use crate::{
+    cells::Cells,
+    data_structure::SBF,
+    error::Error,
+    types::{Area, HashFunction, IndexMode, InputMode, SaltKey, Salts, CACHE_LINE_BYTES},
+};
+
+/// Magic bytes at the start of each file
+const MAGIC: [u8; 4] = *b"SBF\0";
+/// Version of the format written by this crate
+const VERSION: u16 = 2;
+/// First version padding the header to a multiple of [`CACHE_LINE_BYTES`]
+const PADDED_VERSION: u16 = 2;
+/// Marker of the little endian encoding
+const LITTLE_ENDIAN: u8 = 1;
+/// Kind of stored salts
+const STORED_SALTS: u8 = 0;
+/// Kind of salts derived from a master key
+const DERIVED_SALTS: u8 = 1;
+/// Number of bytes of cells read at once
+const CHUNK_BYTES: usize = 1 << 16;
+
+/// Writer computing the checksum and the number of the written bytes
+struct ChecksumWriter<W> {
+    inner: W,
+    hasher: Hasher,
+    written: usize,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(buf.get(..written).unwrap_or_default());
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reader computing the checksum of the read bytes
+struct ChecksumReader<R> {
+    inner: R,
+    hasher: Hasher,
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(buf.get(..read).unwrap_or_default());
+        Ok(read)
+    }
+}
+
+/// Reader counting the read bytes
+struct CountingReader<R> {
+    inner: R,
+    read: usize,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read;
+        Ok(read)
+    }
+}
+
+/// Returns the number of padding bytes following a header of the given length
+fn padding(header_len: usize) -> usize {
+    (CACHE_LINE_BYTES - header_len % CACHE_LINE_BYTES) % CACHE_LINE_BYTES
+}
+
+/// Wraps a format error in an I/O error
+pub(crate) fn invalid(error: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Converts a length read from the file
+fn read_len<R: Read>(reader: &mut R) -> io::Result<usize> {
+    reader
+        .read_u64::<LittleEndian>()?
+        .try_into()
+        .map_err(|_| invalid(Error::CorruptData))
+}
+
+/// Returns the identifier of the hash function
+fn hash_function_id(hash_function: HashFunction) -> u8 {
+    match hash_function {
+        #[cfg(feature = "md5_hash")]
+        HashFunction::MD5 => 1,
+        #[cfg(feature = "md4_hash")]
+        HashFunction::MD4 => 2,
+        #[cfg(feature = "sha256_hash")]
+        HashFunction::SHA256 => 3,
+        #[cfg(feature = "blake3_hash")]
+        HashFunction::BLAKE3 => 4,
+        #[cfg(feature = "xxh3_hash")]
+        HashFunction::XXH3 => 5,
+        #[cfg(feature = "siphash_hash")]
+        HashFunction::SipHash => 6,
+        #[cfg(feature = "sha1_hash")]
+        HashFunction::SHA1 => 7,
+    }
+}
+
+/// Returns the hash function of the identifier, if its feature is enabled
+fn hash_function_from_id(id: u8) -> Option<HashFunction> {
+    match id {
+        #[cfg(feature = "md5_hash")]
+        1 => Some(HashFunction::MD5),
+        #[cfg(feature = "md4_hash")]
+        2 => Some(HashFunction::MD4),
+        #[cfg(feature = "sha256_hash")]
+        3 => Some(HashFunction::SHA256),
+        #[cfg(feature = "blake3_hash")]
+        4 => Some(HashFunction::BLAKE3),
+        #[cfg(feature = "xxh3_hash")]
+        5 => Some(HashFunction::XXH3),
+        #[cfg(feature = "siphash_hash")]
+        6 => Some(HashFunction::SipHash),
+        #[cfg(feature = "sha1_hash")]
+        7 => Some(HashFunction::SHA1),
+        _ => None,
+    }
+}
+
+/// Counters of the metrics stored in the file
+#[derive(Default)]
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+struct MetricsCounters {
+    members: usize,
+    collisions: usize,
+    area_members: Vec<usize>,
+    area_self_collisions: Vec<usize>,
+}
+
+/// Parameters and salts of a filter, stored before its cells
+pub(crate) struct Header {
+    hash_function: HashFunction,
+    index_mode: IndexMode,
+    input_mode: InputMode,
+    /// Number of bytes of each cell
+    pub(crate) width: usize,
+    /// Number of cells
+    pub(crate) cells: usize,
+    area_number: usize,
+    salts: Salts,
+    counters: Option<MetricsCounters>,
+}
+
+impl Header {
+    /// Reads the header, leaving the reader at the start of the cells
+    pub(crate) fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        let r = &mut CountingReader { inner: r, read: 0 };
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid(Error::CorruptData));
+        }
+        let version = r.read_u16::<LittleEndian>()?;
+        if version > VERSION {
+            return Err(invalid(Error::UnsupportedVersion));
+        }
+        if r.read_u8()? != LITTLE_ENDIAN {
+            return Err(invalid(Error::CorruptData));
+        }
+        let hash_function = hash_function_from_id(r.read_u8()?)
+            .ok_or_else(|| invalid(Error::MissingHashFunction))?;
+        let index_mode = match r.read_u8()? {
+            0 => IndexMode::Salted,
+            1 => IndexMode::DoubleHashing,
+            2 => IndexMode::LibSbf,
+            3 => IndexMode::Blocked,
+            _ => return Err(invalid(Error::CorruptData)),
+        };
+        let input_mode = match r.read_u8()? {
+            0 => InputMode::Xor,
+            1 => InputMode::Streamed,
+            2 => InputMode::LibSbf,
+            _ => return Err(invalid(Error::CorruptData)),
+        };
+        let width = match r.read_u8()? {
+            width @ (1 | 2 | 4 | 8) => width as usize,
+            _ => return Err(invalid(Error::CorruptData)),
+        };
+        let cells = read_len(r)?;
+        let area_number = r
+            .read_u64::<LittleEndian>()?
+            .try_into()
+            .unwrap_or(usize::MAX);
+        // The areas, empty area included, must fit in the cells
+        if area_number < 2 || (width < 8 && area_number > 1 << (8 * width)) {
+            return Err(invalid(Error::CorruptData));
+        }
+
+        let salts = match r.read_u8()? {
+            STORED_SALTS => {
+                let hash_number = read_len(r)?;
+                let mut salts = Vec::new();
+                for _ in 0..hash_number {
+                    let len = r.read_u64::<LittleEndian>()?;
+                    let mut salt = Vec::new();
+                    r.take(len).read_to_end(&mut salt)?;
+                    if salt.len() as u64 != len {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    salts.push(salt);
+                }
+                Salts::Stored(salts)
+            }
+            DERIVED_SALTS => {
+                let mut key: SaltKey = [0; 32];
+                r.read_exact(&mut key)?;
+                Salts::Derived {
+                    key,
+                    hash_number: read_len(r)?,
+                    max_input_size: read_len(r)?,
+                }
+            }
+            _ => return Err(invalid(Error::CorruptData)),
+        };
+
+        let counters = match r.read_u8()? {
+            0 => None,
+            1 => {
+                let mut counters = MetricsCounters {
+                    members: read_len(r)?,
+                    collisions: read_len(r)?,
+                    ..Default::default()
+                };
+                // The counters are collected as they are read, so a corrupted number of areas
+                // fails at the end of the file instead of allocating the metrics
+                for _ in 0..read_len(r)? {
+                    counters.area_members.push(read_len(r)?);
+                    counters.area_self_collisions.push(read_len(r)?);
+                }
+                if counters.area_members.len() != area_number {
+                    return Err(invalid(Error::CorruptData));
+                }
+                Some(counters)
+            }
+            _ => return Err(invalid(Error::CorruptData)),
+        };
+
+        if version >= PADDED_VERSION {
+            let mut bytes = [0; CACHE_LINE_BYTES];
+            let bytes = &mut bytes[..padding(r.read)];
+            r.read_exact(bytes)?;
+            if bytes.iter().any(|byte| *byte != 0) {
+                return Err(invalid(Error::CorruptData));
+            }
+        }
+
+        Ok(Header {
+            hash_function,
+            index_mode,
+            input_mode,
+            width,
+            cells,
+            area_number,
+            salts,
+            counters,
+        })
+    }
+
+    /// Builds the filter with the given cells, dropping the metrics counters if `metrics` is
+    /// `false`
+    pub(crate) fn into_sbf<U: Area>(
+        self,
+        filter: Cells<U>,
+        metrics: bool,
+    ) -> io::Result<SBF<U, HashFunction>> {
+        if self.cells == 0 || self.salts.is_empty() || !self.index_mode.supports_cells(self.cells) {
+            return Err(invalid(Error::CorruptData));
+        }
+        if self.area_number > SBF::<U>::max_area_number() {
+            return Err(invalid(Error::TooManyAreas));
+        }
+        let counters = self.counters.filter(|_| metrics);
+
+        // The cells of mapped filters are not loaded, so the index mode is set without checking
+        // them again
+        let mut sbf = SBF::from_cells(
+            filter,
+            self.salts,
+            self.hash_function,
+            self.area_number,
+            cfg!(feature = "metrics") && counters.is_some(),
+        )
+        .with_input_mode(self.input_mode);
+        sbf.set_index_mode(self.index_mode);
+
+        #[cfg(feature = "metrics")]
+        if let Some(counters) = counters {
+            let metrics = &mut sbf.metrics;
+            metrics.members = counters.members;
+            metrics.collisions = counters.collisions;
+            metrics.area_members = counters.area_members;
+            metrics.area_self_collisions = counters.area_self_collisions;
+            metrics.count_area_cells(&sbf.filter);
+        }
+
+        Ok(sbf)
+    }
+}
+
+impl<U> SBF<U, HashFunction>
+where
+    U: Area,
+{
+    /// Writes the filter in the binary format described in the [module](crate::binary)
+    /// documentation.
+    ///
+    /// Area types wider than 64 bits are not supported and are reported as an
+    /// [`io::ErrorKind::InvalidData`] error wrapping [`Error::TooManyAreas`].
+    /// The writer is buffered internally.
+    /// Metrics not tracking the areas of the filter, as in filters deserialized from versions of
+    /// the crate that did not store the number of areas, are not written.
+    pub fn write_to(&self, writer: impl Write) -> io::Result<()> {
+        let mut writer = ChecksumWriter {
+            inner: BufWriter::new(writer),
+            hasher: Hasher::new(),
+            written: 0,
+        };
+        let w = &mut writer;
+
+        w.write_all(&MAGIC)?;
+        w.write_u16::<LittleEndian>(VERSION)?;
+        w.write_u8(LITTLE_ENDIAN)?;
+        w.write_u8(hash_function_id(self.hash_function))?;
+        w.write_u8(match self.index_mode {
+            IndexMode::Salted => 0,
+            IndexMode::DoubleHashing => 1,
+            IndexMode::LibSbf => 2,
+            IndexMode::Blocked => 3,
+        })?;
+        w.write_u8(match self.input_mode {
+            InputMode::Xor => 0,
+            InputMode::Streamed => 1,
+            InputMode::LibSbf => 2,
+        })?;
+        let width = match std::mem::size_of::<U>() {
+            width @ (1 | 2 | 4 | 8) => width,
+            _ => return Err(invalid(Error::TooManyAreas)),
+        };
+        w.write_u8(width as u8)?;
+        w.write_u64::<LittleEndian>(self.filter.len() as u64)?;
+        w.write_u64::<LittleEndian>(self.area_number() as u64)?;
+
+        match &self.salts {
+            Salts::Stored(salts) => {
+                w.write_u8(STORED_SALTS)?;
+                w.write_u64::<LittleEndian>(salts.len() as u64)?;
+                for salt in salts {
+                    w.write_u64::<LittleEndian>(salt.len() as u64)?;
+                    w.write_all(salt)?;
+                }
+            }
+            Salts::Derived {
+                key,
+                hash_number,
+                max_input_size,
+            } => {
+                w.write_u8(DERIVED_SALTS)?;
+                w.write_all(key)?;
+                w.write_u64::<LittleEndian>(*hash_number as u64)?;
+                w.write_u64::<LittleEndian>(*max_input_size as u64)?;
+            }
+        }
+
+        self.write_metrics(w)?;
+        w.write_all(&[0; CACHE_LINE_BYTES][..padding(w.written)])?;
+
+        for cell in &self.filter {
+            let cell = cell.to_u64().ok_or_else(|| invalid(Error::CorruptData))?;
+            w.write_uint::<LittleEndian>(cell, width)?;
+        }
+
+        let checksum = writer.hasher.finalize();
+        writer.inner.write_u32::<LittleEndian>(checksum)?;
+        writer.inner.flush()
+    }
+
+    /// Writes the counters of the metrics, if enabled and tracking the areas of the filter
+    fn write_metrics<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        #[cfg(feature = "metrics")]
+        if self.metrics_enabled && self.metrics.area_members.len() == self.area_number() {
+            let metrics = &self.metrics;
+            w.write_u8(1)?;
+            w.write_u64::<LittleEndian>(metrics.members as u64)?;
+            w.write_u64::<LittleEndian>(metrics.collisions as u64)?;
+            w.write_u64::<LittleEndian>(metrics.area_members.len() as u64)?;
+            for area in 0..metrics.area_members.len() {
+                let self_collisions = metrics.area_self_collisions.get(area);
+                w.write_u64::<LittleEndian>(metrics.area_members[area] as u64)?;
+                w.write_u64::<LittleEndian>(self_collisions.copied().unwrap_or_default() as u64)?;
+            }
+            return Ok(());
+        }
+        w.write_u8(0)
+    }
+
+    /// Reads a filter written with [`write_to`](SBF::write_to).
+    ///
+    /// Files written by older versions of the crate are supported.
+    /// The reader is buffered internally, so it may be read past the end of the filter.
+    /// Format errors are reported as [`io::ErrorKind::InvalidData`] errors wrapping an
+    /// [`Error`]:
+    /// - [`Error::CorruptData`] if the file is not a filter, its checksum does not match or a
+    ///   cell holds an area out of the number of areas,
+    /// - [`Error::UnsupportedVersion`] if the file was written by a newer version of the crate,
+    /// - [`Error::MissingHashFunction`] if the feature of the hash function is not enabled,
+    /// - [`Error::TooManyAreas`] if the cells do not fit in the area type `U`.
+    ///
+    /// The metrics are restored only if the `metrics` feature is enabled, the occupied cells are
+    /// recomputed from the cells.
+    pub fn read_from(reader: impl Read) -> io::Result<Self> {
+        let mut reader = ChecksumReader {
+            inner: BufReader::new(reader),
+            hasher: Hasher::new(),
+        };
+        let r = &mut reader;
+
+        let header = Header::read(r)?;
+
+        // The cells are read in chunks and collected as they are read, so a corrupted number of
+        // cells fails at the end of the file instead of allocating the whole filter
+        let mut filter = Cells::default();
+        let mut chunk = vec![0; CHUNK_BYTES / header.width * header.width];
+        let mut remaining = header.cells;
+        while remaining > 0 {
+            let cells = remaining.min(chunk.len() / header.width);
+            let bytes = &mut chunk[..cells * header.width];
+            r.read_exact(bytes)?;
+            for cell in bytes.chunks_exact(header.width) {
+                let cell = LittleEndian::read_uint(cell, header.width);
+                if cell >= header.area_number as u64 {
+                    return Err(invalid(Error::CorruptData));
+                }
+                filter.push(U::from_u64(cell).ok_or_else(|| invalid(Error::TooManyAreas))?);
+            }
+            remaining -= cells;
+        }
+
+        let checksum = reader.hasher.finalize();
+        if reader.inner.read_u32::<LittleEndian>()? != checksum {
+            return Err(invalid(Error::CorruptData));
+        }
+        header.into_sbf(filter, true)
+    }
+}
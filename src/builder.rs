@@ -0,0 +1,198 @@
+//! SBF builder module
+
+use std::marker::PhantomData;
+
+use rand::{rngs::OsRng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+use crate::{
+    data_structure::SBF,
+    error::Error,
+    hasher::SbfHasher,
+    types::{Area, HashFunction, IndexMode, InputMode, SaltKey, Salts},
+};
+
+/// Source of the salts of the built filter
+#[derive(Clone, Copy, Debug)]
+enum SaltSource {
+    /// Salts drawn from the operating system RNG
+    Random,
+    /// Salts drawn from a ChaCha20 RNG seeded with the given seed
+    Seed(u64),
+    /// Salts derived on the fly from the given master key
+    Key(SaltKey),
+}
+
+/// Builder of [`SBF`] data structures
+///
+/// All the parameters are named and validated when the filter is built, so invalid combinations
+/// are reported as errors instead of panicking.
+///
+/// - `cells`, `hash_number`, `max_input_size` and `hash_function` are required,
+/// - `area_number` is the number of areas including the empty area `0`, it defaults to the
+///   largest number of areas that fits in `U` and is required if metrics are enabled,
+/// - salts are random unless a `seed` or a `key` is given,
+/// - metrics are disabled by default and require the `metrics` feature.
+#[derive(Clone, Debug)]
+pub struct SbfBuilder<U, H = HashFunction> {
+    cells: usize,
+    hash_number: usize,
+    max_input_size: usize,
+    hash_function: Option<H>,
+    area_number: Option<usize>,
+    salt_source: SaltSource,
+    index_mode: IndexMode,
+    input_mode: InputMode,
+    metrics: bool,
+    area_type: PhantomData<U>,
+}
+
+impl<U, H> Default for SbfBuilder<U, H> {
+    fn default() -> Self {
+        SbfBuilder {
+            cells: 0,
+            hash_number: 0,
+            max_input_size: 0,
+            hash_function: None,
+            area_number: None,
+            salt_source: SaltSource::Random,
+            index_mode: IndexMode::default(),
+            input_mode: InputMode::default(),
+            metrics: false,
+            area_type: PhantomData,
+        }
+    }
+}
+
+impl<U, H> SbfBuilder<U, H>
+where
+    H: SbfHasher,
+    U: Area,
+{
+    /// Creates a builder with no parameters set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of cells in the filter
+    pub fn cells(mut self, cells: usize) -> Self {
+        self.cells = cells;
+        self
+    }
+
+    /// Sets the number of hash functions used
+    pub fn hash_number(mut self, hash_number: usize) -> Self {
+        self.hash_number = hash_number;
+        self
+    }
+
+    /// Sets the maximum input dimension, i.e. the length of each salt
+    pub fn max_input_size(mut self, max_input_size: usize) -> Self {
+        self.max_input_size = max_input_size;
+        self
+    }
+
+    /// Sets the hash function used to compute the cell indexes
+    pub fn hash_function<G: SbfHasher>(self, hash_function: G) -> SbfBuilder<U, G> {
+        SbfBuilder {
+            cells: self.cells,
+            hash_number: self.hash_number,
+            max_input_size: self.max_input_size,
+            hash_function: Some(hash_function),
+            area_number: self.area_number,
+            salt_source: self.salt_source,
+            index_mode: self.index_mode,
+            input_mode: self.input_mode,
+            metrics: self.metrics,
+            area_type: PhantomData,
+        }
+    }
+
+    /// Sets the number of areas, including the empty area `0`
+    pub fn area_number(mut self, area_number: usize) -> Self {
+        self.area_number = Some(area_number);
+        self
+    }
+
+    /// Derives the salts deterministically from the given seed
+    ///
+    /// See [`SBF::new_seeded`].
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.salt_source = SaltSource::Seed(seed);
+        self
+    }
+
+    /// Derives the salts on the fly from the given master key
+    ///
+    /// See [`SBF::new_with_key`].
+    pub fn key(mut self, key: SaltKey) -> Self {
+        self.salt_source = SaltSource::Key(key);
+        self
+    }
+
+    /// Sets the strategy used to derive the cell indexes
+    pub fn index_mode(mut self, index_mode: IndexMode) -> Self {
+        self.index_mode = index_mode;
+        self
+    }
+
+    /// Sets the strategy used to combine the salts with the inputs
+    pub fn input_mode(mut self, input_mode: InputMode) -> Self {
+        self.input_mode = input_mode;
+        self
+    }
+
+    /// Enables or disables the metrics of the filter
+    pub fn metrics(mut self, metrics: bool) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Validates the parameters and builds the filter
+    pub fn build(self) -> Result<SBF<U, H>, Error> {
+        if self.cells == 0 {
+            return Err(Error::ZeroCells);
+        }
+        if self.hash_number == 0 {
+            return Err(Error::ZeroHashes);
+        }
+        if self.max_input_size == 0 {
+            return Err(Error::ZeroInputSize);
+        }
+        if self.metrics && !cfg!(feature = "metrics") {
+            return Err(Error::MetricsUnavailable);
+        }
+        let hash_function = self.hash_function.ok_or(Error::MissingHashFunction)?;
+
+        let max_area_number = SBF::<U, H>::max_area_number();
+        let area_number = match self.area_number {
+            Some(area_number) if area_number < 2 => return Err(Error::NotEnoughAreas),
+            Some(area_number) if area_number > max_area_number => return Err(Error::TooManyAreas),
+            Some(area_number) => area_number,
+            None if self.metrics => return Err(Error::MissingAreaNumber),
+            None => max_area_number,
+        };
+
+        let salts = match self.salt_source {
+            SaltSource::Random => {
+                Salts::generate(&mut OsRng, self.hash_number, self.max_input_size)
+            }
+            SaltSource::Seed(seed) => Salts::generate(
+                &mut ChaCha20Rng::seed_from_u64(seed),
+                self.hash_number,
+                self.max_input_size,
+            ),
+            SaltSource::Key(key) => Salts::Derived {
+                key,
+                hash_number: self.hash_number,
+                max_input_size: self.max_input_size,
+            },
+        };
+
+        Ok(
+            SBF::from_parts(self.cells, salts, hash_function, area_number, self.metrics)
+                .with_index_mode(self.index_mode)?
+                .with_input_mode(self.input_mode),
+        )
+    }
+}
@@ -0,0 +1,135 @@
+//! Cache-aligned storage of the cells of a filter
+
+use std::fmt::{self, Debug};
+use std::ops::{Deref, DerefMut};
+
+use num::Zero;
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::types::CACHE_LINE_BYTES;
+
+/// Number of cells of each stored block, enough to fill a cache line with cells of any width
+const BLOCK_CELLS: usize = CACHE_LINE_BYTES;
+
+/// Cells stored together, aligned to a cache line
+///
+/// The size of the block is a multiple of its alignment, so consecutive blocks have no padding
+/// between them and their cells are contiguous.
+#[derive(Clone, Copy)]
+#[repr(C, align(64))]
+struct Block<U>([U; BLOCK_CELLS]);
+
+// The alignment of the blocks must be kept in sync with the cache line size
+const _: () = assert!(std::mem::align_of::<Block<u8>>() == CACHE_LINE_BYTES);
+
+/// Cells of a filter
+///
+/// The cells are stored in blocks aligned to the cache lines, so each block of
+/// [`IndexMode::Blocked`](crate::IndexMode::Blocked) fills exactly one cache line.
+/// Dereferences to the slice of the cells, the unused cells of the last block are always `0`.
+#[derive(Clone)]
+pub(crate) struct Cells<U> {
+    blocks: Vec<Block<U>>,
+    len: usize,
+}
+
+impl<U: Copy + Zero> Cells<U> {
+    /// Creates `len` empty cells
+    pub(crate) fn new(len: usize) -> Self {
+        Cells {
+            blocks: vec![Block([U::zero(); BLOCK_CELLS]); len.div_ceil(BLOCK_CELLS)],
+            len,
+        }
+    }
+
+    /// Appends a cell
+    pub(crate) fn push(&mut self, cell: U) {
+        let (block, offset) = (self.len / BLOCK_CELLS, self.len % BLOCK_CELLS);
+        if offset == 0 {
+            self.blocks.push(Block([U::zero(); BLOCK_CELLS]));
+        }
+        self.blocks[block].0[offset] = cell;
+        self.len += 1;
+    }
+}
+
+impl<U> Default for Cells<U> {
+    fn default() -> Self {
+        Cells {
+            blocks: Vec::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<U> Deref for Cells<U> {
+    type Target = [U];
+
+    fn deref(&self) -> &[U] {
+        // SAFETY: the blocks are `repr(C)` arrays of cells without padding between them, so they
+        // are `len` or more contiguous and initialized cells
+        unsafe { std::slice::from_raw_parts(self.blocks.as_ptr().cast::<U>(), self.len) }
+    }
+}
+
+impl<U> DerefMut for Cells<U> {
+    fn deref_mut(&mut self) -> &mut [U] {
+        // SAFETY: see `deref`
+        unsafe { std::slice::from_raw_parts_mut(self.blocks.as_mut_ptr().cast::<U>(), self.len) }
+    }
+}
+
+impl<U: Debug> Debug for Cells<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl<U: PartialEq> PartialEq for Cells<U> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<U: Eq> Eq for Cells<U> {}
+
+impl<U: Copy + Zero> From<Vec<U>> for Cells<U> {
+    fn from(cells: Vec<U>) -> Self {
+        let mut blocks = Cells::new(cells.len());
+        blocks.copy_from_slice(&cells);
+        blocks
+    }
+}
+
+impl<U: Copy + Zero> FromIterator<U> for Cells<U> {
+    fn from_iter<I: IntoIterator<Item = U>>(iter: I) -> Self {
+        let mut cells = Cells::default();
+        iter.into_iter().for_each(|cell| cells.push(cell));
+        cells
+    }
+}
+
+impl<'a, U> IntoIterator for &'a Cells<U> {
+    type Item = &'a U;
+    type IntoIter = std::slice::Iter<'a, U>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// The cells are serialized as a sequence, as a `Vec`
+#[cfg(feature = "serde_support")]
+impl<U: Serialize> Serialize for Cells<U> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (**self).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde_support")]
+impl<'de, U: Copy + Zero + Deserialize<'de>> Deserialize<'de> for Cells<U> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Cells::from)
+    }
+}
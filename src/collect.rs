@@ -0,0 +1,88 @@
+//! Iterator integrations for the SBF data structure
+//!
+//! `SBF` implements [`Extend`] and [`ParallelExtend`] for `(content, area)` pairs, while
+//! [`SbfCollect`] and [`SbfParCollect`] build a new filter from an iterator. A filter cannot be
+//! built with [`FromIterator`] because its parameters must be provided, use
+//! [`collect_with`](SbfCollect::collect_with) and a [`SbfBuilder`] instead.
+
+use rayon::prelude::*;
+
+use crate::{
+    builder::SbfBuilder, data_structure::SBF, error::Error, hasher::SbfHasher, types::Area,
+};
+
+/// Inserts all the pairs with [`SBF::insert_batch`].
+///
+/// # Panics
+///
+/// Panics if any of the pairs cannot be inserted, the pairs processed before it stay inserted.
+/// Use [`SBF::insert_batch`] to handle the error instead.
+impl<U, H, K> Extend<(K, U)> for SBF<U, H>
+where
+    H: SbfHasher,
+    K: AsRef<[u8]> + Send,
+    U: Area,
+{
+    fn extend<I: IntoIterator<Item = (K, U)>>(&mut self, iter: I) {
+        if let Err(e) = self.insert_batch(iter) {
+            panic!("Failed to extend the filter: {}", e)
+        }
+    }
+}
+
+/// Inserts all the pairs with [`SBF::par_insert_batch`].
+///
+/// # Panics
+///
+/// Panics if any of the pairs cannot be inserted, the pairs processed before it stay inserted.
+/// Use [`SBF::par_insert_batch`] to handle the error instead.
+impl<U, H, K> ParallelExtend<(K, U)> for SBF<U, H>
+where
+    H: SbfHasher,
+    K: AsRef<[u8]> + Send,
+    U: Area,
+{
+    fn par_extend<I: IntoParallelIterator<Item = (K, U)>>(&mut self, par_iter: I) {
+        if let Err(e) = self.par_insert_batch(par_iter) {
+            panic!("Failed to extend the filter: {}", e)
+        }
+    }
+}
+
+/// Collects `(content, area)` pairs into a new filter
+pub trait SbfCollect<K, U: Area>: Iterator<Item = (K, U)> + Sized {
+    /// Builds a filter with the given builder and inserts all the pairs in it
+    fn collect_with<H: SbfHasher>(self, builder: SbfBuilder<U, H>) -> Result<SBF<U, H>, Error>;
+}
+
+impl<I, K, U> SbfCollect<K, U> for I
+where
+    I: Iterator<Item = (K, U)>,
+    K: AsRef<[u8]> + Send,
+    U: Area,
+{
+    fn collect_with<H: SbfHasher>(self, builder: SbfBuilder<U, H>) -> Result<SBF<U, H>, Error> {
+        let mut sbf = builder.build()?;
+        sbf.insert_batch(self)?;
+        Ok(sbf)
+    }
+}
+
+/// Collects parallel `(content, area)` pairs into a new filter
+pub trait SbfParCollect<K, U: Area>: ParallelIterator<Item = (K, U)> {
+    /// Builds a filter with the given builder and inserts all the pairs in it
+    fn collect_with<H: SbfHasher>(self, builder: SbfBuilder<U, H>) -> Result<SBF<U, H>, Error>;
+}
+
+impl<I, K, U> SbfParCollect<K, U> for I
+where
+    I: ParallelIterator<Item = (K, U)>,
+    K: AsRef<[u8]> + Send,
+    U: Area,
+{
+    fn collect_with<H: SbfHasher>(self, builder: SbfBuilder<U, H>) -> Result<SBF<U, H>, Error> {
+        let mut sbf = builder.build()?;
+        sbf.par_insert_batch(self)?;
+        Ok(sbf)
+    }
+}
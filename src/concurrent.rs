@@ -0,0 +1,244 @@
+//! Lock-free SBF data structure
+//!
+//! [`ConcurrentSBF`] stores its cells in atomic integers, so inputs can be inserted and checked
+//! from multiple threads through a shared reference, without wrapping the filter in a lock.
+//! The insertion uses `fetch_max`, which keeps the highest area on each cell just like
+//! [`SBF::insert`].
+
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+use crate::{
+    data_structure::{lowest_cell, SBF},
+    error::Error,
+    hasher::SbfHasher,
+    types::{Area, HashFunction},
+};
+
+/// Area type that can be stored in an atomic cell
+pub trait AtomicArea: Area {
+    /// Atomic integer holding an area
+    type Atomic: Debug + Send + Sync;
+
+    /// Creates an atomic cell holding the area
+    fn new_atomic(area: Self) -> Self::Atomic;
+
+    /// Loads the area held by the atomic cell
+    fn load(cell: &Self::Atomic) -> Self;
+
+    /// Stores the maximum between the area held by the atomic cell and the given one, returning
+    /// the previous area
+    fn fetch_max(cell: &Self::Atomic, area: Self) -> Self;
+
+    /// Consumes the atomic cell, returning the area it holds
+    fn into_inner(cell: Self::Atomic) -> Self;
+}
+
+macro_rules! impl_atomic_area {
+    ($($area:ty => $atomic:ty),* $(,)?) => {$(
+        impl AtomicArea for $area {
+            type Atomic = $atomic;
+
+            fn new_atomic(area: Self) -> Self::Atomic {
+                <$atomic>::new(area)
+            }
+
+            fn load(cell: &Self::Atomic) -> Self {
+                cell.load(Ordering::Relaxed)
+            }
+
+            fn fetch_max(cell: &Self::Atomic, area: Self) -> Self {
+                cell.fetch_max(area, Ordering::Relaxed)
+            }
+
+            fn into_inner(cell: Self::Atomic) -> Self {
+                cell.into_inner()
+            }
+        }
+    )*};
+}
+
+impl_atomic_area!(
+    u8 => AtomicU8,
+    u16 => AtomicU16,
+    u32 => AtomicU32,
+    u64 => AtomicU64,
+    usize => AtomicUsize,
+);
+
+/// Atomic counters of the metrics of a concurrent filter
+#[cfg(feature = "metrics")]
+#[derive(Debug)]
+struct AtomicMetrics {
+    members: AtomicUsize,
+    collisions: AtomicUsize,
+    area_members: Vec<AtomicUsize>,
+    area_self_collisions: Vec<AtomicUsize>,
+}
+
+#[cfg(feature = "metrics")]
+impl AtomicMetrics {
+    fn new(metrics: &Metrics) -> Self {
+        let atomics = |counters: &[usize]| counters.iter().copied().map(AtomicUsize::new).collect();
+        AtomicMetrics {
+            members: AtomicUsize::new(metrics.members),
+            collisions: AtomicUsize::new(metrics.collisions),
+            area_members: atomics(&metrics.area_members),
+            area_self_collisions: atomics(&metrics.area_self_collisions),
+        }
+    }
+
+    /// Writes the counters in the metrics
+    fn store(&self, metrics: &mut Metrics) {
+        let values =
+            |counters: &[AtomicUsize]| counters.iter().map(|c| c.load(Ordering::Relaxed)).collect();
+        metrics.members = self.members.load(Ordering::Relaxed);
+        metrics.collisions = self.collisions.load(Ordering::Relaxed);
+        metrics.area_members = values(&self.area_members);
+        metrics.area_self_collisions = values(&self.area_self_collisions);
+    }
+
+    fn increment(counter: Option<&AtomicUsize>) {
+        if let Some(counter) = counter {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Spatial Bloom Filter that can be shared between threads
+///
+/// Built from an [`SBF`], whose parameters and salts it reuses, and converted back into one with
+/// [`into_sbf`](ConcurrentSBF::into_sbf).
+/// Each cell is updated atomically, but the cells of an input are not updated together: a check
+/// running concurrently with the insertion of the same input may miss it.
+#[derive(Debug)]
+pub struct ConcurrentSBF<U, H = HashFunction>
+where
+    U: AtomicArea,
+{
+    /// Parameters and salts of the filter, its cells are moved in `cells`
+    sbf: SBF<U, H>,
+    /// Atomic cells
+    cells: Vec<U::Atomic>,
+    #[cfg(feature = "metrics")]
+    /// Atomic counters of the metrics, if enabled
+    metrics: Option<AtomicMetrics>,
+}
+
+impl<U, H> ConcurrentSBF<U, H>
+where
+    H: SbfHasher,
+    U: AtomicArea,
+{
+    /// Builds a concurrent filter with the content, parameters and salts of the filter
+    pub fn new(mut sbf: SBF<U, H>) -> Self {
+        let cells = std::mem::take(&mut sbf.filter)
+            .iter()
+            .copied()
+            .map(U::new_atomic)
+            .collect();
+        ConcurrentSBF {
+            #[cfg(feature = "metrics")]
+            metrics: sbf
+                .metrics_enabled
+                .then(|| AtomicMetrics::new(&sbf.metrics)),
+            sbf,
+            cells,
+        }
+    }
+
+    /// Converts the concurrent filter back into a filter
+    pub fn into_sbf(self) -> SBF<U, H> {
+        let mut sbf = self.sbf;
+        sbf.filter = self.cells.into_iter().map(U::into_inner).collect();
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.store(&mut sbf.metrics);
+            sbf.metrics.count_area_cells(&sbf.filter);
+        }
+        sbf
+    }
+
+    /// Check an input for presence in the filter.
+    ///
+    /// Returns `0` if the input has not been inserted or the area it belongs to, see
+    /// [`SBF::check`].
+    pub fn check(&self, content: impl AsRef<[u8]>) -> Result<U, Error> {
+        let indexes = self
+            .sbf
+            .calc_indexes_within(content.as_ref(), self.cells.len())?;
+        lowest_cell(&indexes, |i| {
+            self.cells
+                .get(i)
+                .map(U::load)
+                .ok_or(Error::IndexOutOfBounds)
+        })
+    }
+
+    /// Insert the content in the filter associated to the given area, see [`SBF::insert`].
+    pub fn insert(&self, content: impl AsRef<[u8]>, area: U) -> Result<(), Error> {
+        #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+        let area_index = self.sbf.area_index(area)?;
+        let indexes = self
+            .sbf
+            .calc_indexes_within(content.as_ref(), self.cells.len())?;
+        let cells = indexes
+            .iter()
+            .map(|i| self.cells.get(*i).ok_or(Error::IndexOutOfBounds))
+            .collect::<Result<Vec<&U::Atomic>, Error>>()?;
+
+        for cell in cells {
+            #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+            let previous = U::fetch_max(cell, area);
+
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = &self.metrics {
+                if !previous.is_zero() {
+                    AtomicMetrics::increment(Some(&metrics.collisions));
+                }
+                if previous == area {
+                    AtomicMetrics::increment(metrics.area_self_collisions.get(area_index));
+                }
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            AtomicMetrics::increment(Some(&metrics.members));
+            AtomicMetrics::increment(metrics.area_members.get(area_index));
+        }
+
+        Ok(())
+    }
+
+    /// Returns a snapshot of the metrics of the filter
+    ///
+    /// The snapshot is consistent only if no insertion is running concurrently.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Metrics {
+        let mut metrics = self.sbf.metrics.clone();
+        if let Some(counters) = &self.metrics {
+            counters.store(&mut metrics);
+            let cells: Vec<U> = self.cells.iter().map(U::load).collect();
+            metrics.count_area_cells(&cells);
+        }
+        metrics
+    }
+
+    /// Returns the number of areas, including the empty area `0`
+    pub fn area_number(&self) -> usize {
+        self.sbf.area_number()
+    }
+}
+
+impl<U, H> From<SBF<U, H>> for ConcurrentSBF<U, H>
+where
+    H: SbfHasher,
+    U: AtomicArea,
+{
+    fn from(sbf: SBF<U, H>) -> Self {
+        ConcurrentSBF::new(sbf)
+    }
+}
@@ -0,0 +1,286 @@
+//! Counting SBF data structure
+//!
+//! [`CountingSBF`] keeps, for each cell, a counter of the insertions of each area stored in it, so
+//! inserted elements can also be removed.
+//! Each cell still holds the highest area with a non zero counter, so checks answer as
+//! [`SBF::check`].
+
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data_structure::SBF,
+    error::Error,
+    hasher::SbfHasher,
+    types::{Area, HashFunction},
+};
+
+/// Largest number of areas, including the empty one, whose counters are stored densely
+///
+/// The dense counters of a cell then fill at most a cache line.
+const DENSE_AREAS: usize = 17;
+
+/// Spatial Bloom Filter supporting the removal of elements
+///
+/// Built from an empty [`SBF`], whose parameters and salts it reuses.
+/// With up to 16 non empty areas a `u32` counter is kept for each area of each cell, taking
+/// `4 * (area_number - 1)` bytes per cell.
+/// With more areas only the non zero counters are kept, in a map from cell and area to counter
+/// taking roughly 30 bytes for each area inserted in each cell, and nothing for the empty cells.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct CountingSBF<U, H = HashFunction>
+where
+    U: Area,
+{
+    /// Filter holding the highest area with a non zero counter of each cell
+    sbf: SBF<U, H>,
+    /// Insertion counters of the areas of each cell
+    counters: Counters<U>,
+}
+
+impl<U, H> CountingSBF<U, H>
+where
+    H: SbfHasher,
+    U: Area,
+{
+    /// Builds a counting filter with the parameters and salts of the filter.
+    ///
+    /// Returns [`Error::InvalidParameters`] if the filter is not empty, as the insertions it
+    /// contains cannot be counted.
+    pub fn new(sbf: SBF<U, H>) -> Result<Self, Error> {
+        if sbf.filter.iter().any(|cell| !cell.is_zero()) {
+            return Err(Error::InvalidParameters);
+        }
+
+        Ok(CountingSBF {
+            counters: Counters::new(sbf.filter.len(), sbf.area_number()),
+            sbf,
+        })
+    }
+
+    /// Returns the filter holding the highest area of each cell
+    ///
+    /// Can be used to query the filter, or cloned to obtain a plain [`SBF`].
+    pub fn as_sbf(&self) -> &SBF<U, H> {
+        &self.sbf
+    }
+
+    /// Converts the counting filter into a plain filter, dropping the counters
+    pub fn into_sbf(self) -> SBF<U, H> {
+        self.sbf
+    }
+
+    /// Check an input for presence in the filter, see [`SBF::check`].
+    pub fn check(&self, content: impl AsRef<[u8]>) -> Result<&U, Error> {
+        self.sbf.check(content)
+    }
+
+    /// Returns the number of insertions of the area counted by a cell
+    pub fn get_counter(&self, index: usize, area: U) -> Result<u32, Error> {
+        self.sbf.area_index(area)?;
+        if index >= self.sbf.filter.len() {
+            return Err(Error::IndexOutOfBounds);
+        }
+        Ok(self.counters.get(index, area))
+    }
+
+    /// Insert the content in the filter associated to the given area, see [`SBF::insert`].
+    ///
+    /// Returns [`Error::CounterOverflow`] if one of the counters of the content is saturated, in
+    /// which case the filter is not modified.
+    pub fn insert(&mut self, content: impl AsRef<[u8]>, area: U) -> Result<(), Error> {
+        let area_index = self.sbf.area_index(area)?;
+        let indexes = self.sbf.calc_indexes(content.as_ref())?;
+        self.update_counters(&indexes, area, |c| c.checked_add(1), Error::CounterOverflow)?;
+        self.sbf.set_cells(&indexes, area, area_index)
+    }
+
+    /// Removes the content previously inserted with the given area.
+    ///
+    /// Each cell of the content goes back to the highest area still counted on it.
+    /// Returns [`Error::NotPresent`] if the content has not been inserted with the area, in which
+    /// case the filter is not modified.
+    /// Like any removal from a Bloom filter, removing a content that was never inserted but
+    /// collides with inserted ones corrupts the filter.
+    pub fn remove(&mut self, content: impl AsRef<[u8]>, area: U) -> Result<(), Error> {
+        #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+        let area_index = self.sbf.area_index(area)?;
+        let indexes = self.sbf.calc_indexes(content.as_ref())?;
+        self.update_counters(&indexes, area, |c| c.checked_sub(1), Error::NotPresent)?;
+
+        for index in indexes {
+            let highest = self.counters.highest(index);
+            #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+            let previous = self
+                .sbf
+                .filter
+                .get_mut(index)
+                .map(|cell| std::mem::replace(cell, highest))
+                .ok_or(Error::IndexOutOfBounds)?;
+
+            #[cfg(feature = "metrics")]
+            if self.sbf.metrics_enabled && previous != highest {
+                let area_cells = &mut self.sbf.metrics.area_cells;
+                if let Some(cells) = area_cells.get_mut(previous.to_usize().unwrap_or_default()) {
+                    *cells = cells.saturating_sub(1);
+                }
+                if !highest.is_zero() {
+                    if let Some(cells) = area_cells.get_mut(highest.to_usize().unwrap_or_default())
+                    {
+                        *cells += 1;
+                    }
+                }
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        if self.sbf.metrics_enabled {
+            let metrics = &mut self.sbf.metrics;
+            metrics.members = metrics.members.saturating_sub(1);
+            if let Some(members) = metrics.area_members.get_mut(area_index) {
+                *members = members.saturating_sub(1);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the update to the counters of an area of the cells.
+    ///
+    /// The counters are only modified if the update succeeds on all of them.
+    fn update_counters<F>(
+        &mut self,
+        indexes: &[usize],
+        area: U,
+        update: F,
+        error: Error,
+    ) -> Result<(), Error>
+    where
+        F: Fn(u32) -> Option<u32>,
+    {
+        // The same cell may be hit by more than one hash, so the updates are applied to a copy
+        let mut updated: Vec<(usize, u32)> = Vec::with_capacity(indexes.len());
+        for index in indexes {
+            let current = match updated.iter().rev().find(|(i, _)| i == index) {
+                Some((_, value)) => *value,
+                None => self.get_counter(*index, area)?,
+            };
+            updated.push((*index, update(current).ok_or(error)?));
+        }
+
+        for (index, value) in updated {
+            self.counters.set(index, area, value);
+        }
+        Ok(())
+    }
+}
+
+/// Insertion counters of the areas of the cells
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+enum Counters<U: Area> {
+    /// Counters of all the non empty areas, stored cell after cell
+    Dense {
+        /// Number of counters of each cell
+        areas: usize,
+        counters: Vec<u32>,
+    },
+    /// Non zero counters, by cell and area
+    Sparse(
+        #[cfg_attr(
+            feature = "serde_support",
+            serde(
+                with = "entries",
+                bound(serialize = "U: Serialize", deserialize = "U: Deserialize<'de>")
+            )
+        )]
+        BTreeMap<(usize, U), u32>,
+    ),
+}
+
+impl<U: Area> Counters<U> {
+    /// Creates the counters of the cells, dense if the areas are few enough
+    fn new(cells: usize, area_number: usize) -> Self {
+        if area_number <= DENSE_AREAS {
+            let areas = area_number - 1;
+            Counters::Dense {
+                areas,
+                counters: vec![0; cells * areas],
+            }
+        } else {
+            Counters::Sparse(BTreeMap::new())
+        }
+    }
+
+    /// Position of the dense counter of a non empty area of a cell
+    fn position(areas: usize, index: usize, area: U) -> usize {
+        index * areas + area.to_usize().unwrap_or_default() - 1
+    }
+
+    /// Returns the counter of an area of a cell, `0` for the empty area
+    fn get(&self, index: usize, area: U) -> u32 {
+        match self {
+            _ if area.is_zero() => 0,
+            Counters::Dense { areas, counters } => counters[Self::position(*areas, index, area)],
+            Counters::Sparse(counters) => counters.get(&(index, area)).copied().unwrap_or(0),
+        }
+    }
+
+    /// Sets the counter of a non empty area of a cell, zero counters are dropped from a map
+    fn set(&mut self, index: usize, area: U, value: u32) {
+        match self {
+            Counters::Dense { areas, counters } => {
+                counters[Self::position(*areas, index, area)] = value;
+            }
+            Counters::Sparse(counters) if value == 0 => {
+                counters.remove(&(index, area));
+            }
+            Counters::Sparse(counters) => {
+                counters.insert((index, area), value);
+            }
+        }
+    }
+
+    /// Returns the highest area with a non zero counter in a cell, `0` if there is none
+    fn highest(&self, index: usize) -> U {
+        match self {
+            Counters::Dense { areas, counters } => counters[index * areas..(index + 1) * areas]
+                .iter()
+                .rposition(|count| *count > 0)
+                .and_then(|position| U::from_usize(position + 1))
+                .unwrap_or_else(U::zero),
+            Counters::Sparse(counters) => counters
+                .range((index, U::zero())..=(index, U::max_value()))
+                .next_back()
+                .map_or_else(U::zero, |((_, area), _)| *area),
+        }
+    }
+}
+
+/// Serializes the map of the sparse counters as a sequence of entries, as the keys of some
+/// formats cannot be tuples
+#[cfg(feature = "serde_support")]
+mod entries {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(super) fn serialize<U, S>(map: &BTreeMap<(usize, U), u32>, s: S) -> Result<S::Ok, S::Error>
+    where
+        U: Serialize,
+        S: Serializer,
+    {
+        s.collect_seq(map)
+    }
+
+    pub(super) fn deserialize<'de, U, D>(d: D) -> Result<BTreeMap<(usize, U), u32>, D::Error>
+    where
+        U: Ord + Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Vec::<((usize, U), u32)>::deserialize(d).map(|entries| entries.into_iter().collect())
+    }
+}
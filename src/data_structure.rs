@@ -1,221 +1,490 @@
 //! SBF data structure module
 
-use std::sync::Mutex;
-use std::{io::Cursor, ops};
-
-use byteorder::ReadBytesExt;
-#[cfg(feature = "md4_hash")]
-use md4::Digest;
-#[cfg(feature = "md5_hash")]
-use md5::compute as md5_compute;
-use num::{cast::AsPrimitive, Bounded, FromPrimitive, ToPrimitive, Unsigned, Zero};
-use rand::{rngs::OsRng, Rng};
-use rayon::{iter::repeatn, prelude::*};
+use std::sync::{Mutex, PoisonError};
+
+use byteorder::ByteOrder;
+use num::{Bounded, ToPrimitive, Unsigned};
+use rand::{rngs::OsRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use rayon::prelude::*;
 #[cfg(feature = "serde_support")]
 use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "metrics")]
 use crate::metrics::Metrics;
 use crate::{
+    builder::SbfBuilder,
+    cells::Cells,
     error::Error,
-    types::{HashFunction, Salt},
+    hasher::{stable_encode, SbfHasher, StableEncode},
+    types::{
+        Area, Explanation, HashFunction, IndexMode, InputMode, Membership, Probe, Salt, SaltKey,
+        Salts, CACHE_LINE_BYTES,
+    },
 };
 
+/// Number of inputs whose cell indexes are buffered at once by the batch insertions
+pub const BATCH_CHUNK: usize = 1 << 14;
+
 /// Spatial Bloom Filter data structure
 ///
 /// This data structure uses a multi level bloom filter to identify if a content has already been
 /// inserted in the filter and of which of a finite number of disjoint subsets of the origin space
 /// it belongs to.
 /// This is a probabilistic data structure
+///
+/// The hash function is pluggable through the `H` type parameter, any [`SbfHasher`] can be used.
+/// By default the built-in [`HashFunction`] selection is used.
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
-pub struct SBF<U>
+pub struct SBF<U, H = HashFunction>
 where
     U: Unsigned + Bounded + Clone + Copy + PartialOrd + Eq,
 {
     /// Hash salt container
-    salts: Vec<Salt>,
+    pub(crate) salts: Salts,
     /// Filter
-    pub(crate) filter: Vec<U>,
+    pub(crate) filter: Cells<U>,
     /// Hash function to use during the calculation of the insertion and query indexes
-    hash_function: HashFunction,
+    pub(crate) hash_function: H,
+    /// Strategy used to derive the insertion and query indexes from the hash digests
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    pub(crate) index_mode: IndexMode,
+    /// Strategy used to combine the salts with the inputs
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    pub(crate) input_mode: InputMode,
+    /// Number of areas, including the empty area `0`
+    #[cfg_attr(
+        feature = "serde_support",
+        serde(
+            default = "max_area_number::<U>",
+            bound(deserialize = "U: ToPrimitive")
+        )
+    )]
+    pub(crate) area_number: usize,
     #[cfg(feature = "metrics")]
     /// SBF metrics structure
     ///
     /// Can be activated enabling the `metrics` feature.
     /// Can be queried to retrieve information about the state of the filter.
     pub metrics: Metrics,
+    #[cfg(feature = "metrics")]
+    /// Whether the metrics are updated on insertion
+    #[cfg_attr(feature = "serde_support", serde(default = "metrics_enabled_default"))]
+    pub(crate) metrics_enabled: bool,
 }
 
-impl<U> SBF<U>
+impl<U, H> SBF<U, H>
 where
-    U: 'static
-        + Send
-        + Sync
-        + Clone
-        + Copy
-        + Ord
-        + PartialOrd
-        + Eq
-        + Unsigned
-        + Bounded
-        + Zero
-        + FromPrimitive
-        + ToPrimitive
-        + ops::AddAssign
-        + ops::SubAssign,
-    usize: num::cast::AsPrimitive<U>,
+    H: SbfHasher,
+    U: Area,
 {
-    /// Adapter for the hash function used by the filter
-    fn hash(&self, buff: &[u8]) -> Vec<u8> {
-        match &self.hash_function {
-            #[cfg(feature = "md5_hash")]
-            HashFunction::MD5 => md5_compute(buff).to_vec(),
-            #[cfg(feature = "md4_hash")]
-            HashFunction::MD4 => md4::Md4::digest(buff).to_vec(),
+    /// Computes the digest of the content combined with the salt
+    ///
+    /// In [`InputMode::Xor`] the content is padded with zeros to the length of the salt, longer
+    /// contents are rejected.
+    fn salted_digest(&self, content: &[u8], salt: &Salt) -> Result<Vec<u8>, Error> {
+        match self.input_mode {
+            InputMode::Xor => {
+                if content.len() > salt.len() {
+                    return Err(Error::InputTooLong);
+                }
+
+                let xor_content: Vec<u8> = salt
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| content.get(i).copied().unwrap_or(0) ^ v)
+                    .collect();
+
+                Ok(self.hash_function.digest(&xor_content))
+            }
+            InputMode::Streamed => Ok(self.hash_function.digest_parts(&[salt, content])),
+            InputMode::LibSbf => {
+                if content.len() > salt.len() {
+                    return Err(Error::InputTooLong);
+                }
+
+                let xor_content: Vec<u8> = content.iter().zip(salt).map(|(c, s)| c ^ s).collect();
+
+                Ok(self.hash_function.digest(&xor_content))
+            }
         }
     }
 
     /// Calculates the indexed of the cells pointed by each of the hashes generated from the input
-    fn calc_indexes(&self, content: Vec<u8>) -> Vec<U> {
-        self.salts
-            .par_iter()
-            .map(|salt: &Salt| {
-                // Iter over salt u8 values
-                let salt_iterator = salt.par_iter();
-
-                // Repeat 0, the length of the salt is the upper bound
-                let zeros = repeatn(&(0_u8), salt.len());
-
-                // Content input with padding
-                let content = content.par_iter().chain(zeros);
+    ///
+    /// Cell indexes are independent of the area type `U`, so the number of cells is only bounded
+    /// by `usize`.
+    pub(crate) fn calc_indexes(&self, content: &[u8]) -> Result<Vec<usize>, Error> {
+        self.calc_indexes_within(content, self.filter.len())
+    }
 
-                // XORed content
-                let xor_content: Vec<u8> = content.zip(salt_iterator).map(|(h, v)| h ^ v).collect();
+    /// Calculates the indexes of the cells of the input in a filter with the given number of
+    /// cells, the cells themselves may be stored elsewhere
+    pub(crate) fn calc_indexes_within(
+        &self,
+        content: &[u8],
+        cells: usize,
+    ) -> Result<Vec<usize>, Error> {
+        let cells = match cells {
+            0 => return Err(Error::CorruptData),
+            cells => cells as u64,
+        };
+        match self.index_mode {
+            IndexMode::Salted => self.calc_salted_indexes(content, cells),
+            IndexMode::DoubleHashing => self.calc_double_hashing_indexes(content, cells),
+            IndexMode::LibSbf => self.calc_libsbf_indexes(content, cells),
+            IndexMode::Blocked => self.calc_blocked_indexes(content, cells),
+        }
+    }
 
-                // First 8 u8 of the hash
-                let digest = self.hash(&xor_content).drain(0..8).collect::<Vec<u8>>();
+    /// Returns the number of cells of each block of [`IndexMode::Blocked`]
+    ///
+    /// Filters with less cells than a block use a single block as large as the filter.
+    pub fn block_cells() -> usize {
+        (CACHE_LINE_BYTES / std::mem::size_of::<U>()).max(1)
+    }
 
-                // Read digest as a u64
-                let digest_value = Cursor::new(digest)
-                    .read_u64::<byteorder::NativeEndian>()
-                    .unwrap();
+    /// Calculates one index for each salt, using a separate digest for each of them
+    fn calc_salted_indexes(&self, content: &[u8], cells: u64) -> Result<Vec<usize>, Error> {
+        self.salts
+            .iter()
+            .map(|salt| {
+                // Read the first 8 u8 of the hash as a little endian u64
+                let digest = self.salted_digest(content, &salt)?;
+                let digest_value = read_digest::<byteorder::LittleEndian>(&digest, 0)
+                    .ok_or(Error::DigestTooShort)?;
 
                 // Return cell index
-                (digest_value as usize % self.filter.len()).as_()
+                Ok((digest_value % cells) as usize)
             })
-            .collect::<Vec<U>>()
+            .collect::<Result<Vec<usize>, Error>>()
     }
 
-    /// Returns the content of a cell
-    fn get_cell(&self, index: U) -> Result<&U, Error> {
-        self.filter
-            .get(index.to_usize().unwrap())
-            .ok_or(Error::IndexOutOfBounds)
+    /// Calculates one index for each salt as libSBF-cpp does
+    ///
+    /// The first 4 bytes of each digest are read as a little endian `u32`, and its highest
+    /// `bit_mapping` bits are the index, where the filter has `2^bit_mapping` cells.
+    fn calc_libsbf_indexes(&self, content: &[u8], cells: u64) -> Result<Vec<usize>, Error> {
+        if !IndexMode::LibSbf.supports_cells(cells as usize) {
+            return Err(Error::InvalidParameters);
+        }
+        let shift = 32 - cells.trailing_zeros();
+        self.salts
+            .iter()
+            .map(|salt| {
+                let digest = self.salted_digest(content, &salt)?;
+                let digest_value = digest
+                    .get(..4)
+                    .map(byteorder::LittleEndian::read_u32)
+                    .ok_or(Error::DigestTooShort)?;
+
+                Ok((u64::from(digest_value) >> shift) as usize)
+            })
+            .collect::<Result<Vec<usize>, Error>>()
     }
 
-    /// Sets the content of the cell if the input area is higher than the one in the filter
-    fn set_cell(&mut self, index: U, area: U) -> Result<&U, Error> {
-        if let Some(v) = self.filter.get_mut(index.to_usize().unwrap()) {
-            if *v == U::zero() || *v < area {
-                // Cell is not marked or cell has lower value than the input area
-                *v = area;
-            } else if *v >= area {
-                // Cell hash same or higher value than input area
+    /// Calculates the indexes using enhanced double hashing
+    ///
+    /// The two base hashes are read from the first 16 bytes of the digest of the first salt. If the
+    /// digest is shorter than that, the second base hash is read from the digest of the second
+    /// salt.
+    fn calc_double_hashing_indexes(&self, content: &[u8], cells: u64) -> Result<Vec<usize>, Error> {
+        let mut salts = self.salts.iter();
+        let (h1, h2) = match salts.next() {
+            Some(salt) => {
+                let digest = self.salted_digest(content, &salt)?;
+                let h1 = read_digest::<byteorder::LittleEndian>(&digest, 0)
+                    .ok_or(Error::DigestTooShort)?;
+                let h2 = match read_digest::<byteorder::LittleEndian>(&digest, 8) {
+                    Some(h2) => h2,
+                    None => match salts.next() {
+                        Some(salt) => read_digest::<byteorder::LittleEndian>(
+                            &self.salted_digest(content, &salt)?,
+                            0,
+                        )
+                        .ok_or(Error::DigestTooShort)?,
+                        None => 0,
+                    },
+                };
+                (h1, h2)
             }
+            None => return Ok(Vec::new()),
+        };
 
-            #[cfg(feature = "metrics")]
-            {
-                if *v == U::zero() {
-                    // Cell is not marked
-                    self.metrics.area_cells[area.to_usize().unwrap()] += 1;
-                } else if *v < area {
-                    // Cell hash lower value than the input area
-                    // v is not zero ()
-                    if area > U::zero() {
-                        self.metrics.area_cells[v.to_usize().unwrap()] -= 1;
-                    }
-                    self.metrics.area_cells[area.to_usize().unwrap()] += 1;
-                    self.metrics.collisions += 1;
-                } else if *v == area {
-                    // Cell hash same value than input area
-                    self.metrics.collisions += 1;
-                    self.metrics.area_self_collisions[v.to_usize().unwrap()] += 1;
-                } else if *v > area {
-                    // Cell hash higher value than input area
-                    self.metrics.collisions += 1;
-                }
+        Ok(double_hashing(h1, h2, cells)
+            .take(self.salts.len())
+            .map(|index| index as usize)
+            .collect())
+    }
+
+    /// Calculates the indexes of a blocked filter
+    ///
+    /// The first 8 bytes of the digests select the block, the following 16 bytes are the base
+    /// hashes of the enhanced double hashing within the block. The digests of the following salts
+    /// are used if the digest of the first salt is shorter than that, missing base hashes are `0`.
+    ///
+    /// The cells are stored aligned to the cache lines, so each block is a single cache line.
+    fn calc_blocked_indexes(&self, content: &[u8], cells: u64) -> Result<Vec<usize>, Error> {
+        let block_cells = (Self::block_cells() as u64).min(cells);
+        let blocks = cells / block_cells;
+
+        let mut hashes = Vec::with_capacity(3);
+        for salt in self.salts.iter() {
+            let digest = self.salted_digest(content, &salt)?;
+            let words = digest.len() / 8;
+            hashes.extend(
+                (0..words).filter_map(|i| read_digest::<byteorder::LittleEndian>(&digest, i * 8)),
+            );
+            if words == 0 {
+                return Err(Error::DigestTooShort);
+            }
+            if hashes.len() >= 3 {
+                break;
             }
+        }
+        let (block, h2, h3) = match hashes.as_slice() {
+            [] => return Ok(Vec::new()),
+            [h1, rest @ ..] => (
+                h1 % blocks,
+                rest.first().copied().unwrap_or_default(),
+                rest.get(1).copied().unwrap_or_default(),
+            ),
+        };
 
-            Ok(v)
-        } else {
-            Err(Error::IndexOutOfBounds)
+        let start = block * block_cells;
+        Ok(double_hashing(h2, h3, block_cells)
+            .take(self.salts.len())
+            .map(|index| (start + index) as usize)
+            .collect())
+    }
+
+    /// Sets the strategy used to derive the cell indexes.
+    ///
+    /// Must be used right after the construction of the filter, changing the strategy of a filter
+    /// that already contains some elements makes the previous insertions unreachable.
+    /// Returns [`Error::InvalidParameters`] if the strategy does not support the number of cells
+    /// of the filter, see [`IndexMode::LibSbf`].
+    pub fn with_index_mode(mut self, index_mode: IndexMode) -> Result<Self, Error> {
+        if !index_mode.supports_cells(self.filter.len()) {
+            return Err(Error::InvalidParameters);
         }
+        self.set_index_mode(index_mode);
+        Ok(self)
+    }
+
+    /// Sets the strategy used to derive the cell indexes, the number of cells must be supported
+    pub(crate) fn set_index_mode(&mut self, index_mode: IndexMode) {
+        self.index_mode = index_mode;
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics.block_cells = (index_mode == IndexMode::Blocked).then(Self::block_cells);
+        }
+    }
+
+    /// Sets the strategy used to combine the salts with the inputs.
+    ///
+    /// Must be used right after the construction of the filter, changing the strategy of a filter
+    /// that already contains some elements makes the previous insertions unreachable.
+    pub fn with_input_mode(mut self, input_mode: InputMode) -> Self {
+        self.input_mode = input_mode;
+        self
+    }
+
+    /// Returns the content of a cell
+    fn get_cell(&self, index: usize) -> Result<&U, Error> {
+        self.filter.get(index).ok_or(Error::IndexOutOfBounds)
     }
 
     /// Constructor of the SBF data structure
     ///
     /// - `cells`: Number of cells in the filter,
     /// - `hash_number`: Number of hash functions used,
-    /// - `max_input_size`: Maximum input dimension, if a larger one is used it will be truncated,
+    /// - `max_input_size`: Maximum input dimension, larger inputs are rejected unless the
+    ///   [`InputMode::Streamed`] mode is used,
     /// - `hash_function`: Kind of hash function to use,
     /// - `area_number`: Number of different areas (only used in metrics).
+    ///
+    /// The salts are generated by the operating system cryptography safe RNG.
+    /// See [`SBF::builder`] for a validated construction with named parameters.
     pub fn new(
-        cells: U,
+        cells: usize,
         hash_number: usize,
         max_input_size: usize,
-        hash_function: HashFunction,
+        hash_function: H,
         #[cfg(feature = "metrics")] area_number: U,
     ) -> Result<Self, Error> {
-        assert!(cells > U::zero());
+        Self::new_with_rng(
+            cells,
+            hash_number,
+            max_input_size,
+            hash_function,
+            &mut OsRng,
+            #[cfg(feature = "metrics")]
+            area_number,
+        )
+    }
 
-        // Cryptography safe RNG
-        let rng = Mutex::new(OsRng);
+    /// Constructor of the SBF data structure with deterministic salts
+    ///
+    /// The salts are derived from `seed` using the ChaCha20 RNG, so filters built with the same
+    /// parameters and seed share the same hash space, in any process and on any platform.
+    pub fn new_seeded(
+        cells: usize,
+        hash_number: usize,
+        max_input_size: usize,
+        hash_function: H,
+        seed: u64,
+        #[cfg(feature = "metrics")] area_number: U,
+    ) -> Result<Self, Error> {
+        Self::new_with_rng(
+            cells,
+            hash_number,
+            max_input_size,
+            hash_function,
+            &mut ChaCha20Rng::seed_from_u64(seed),
+            #[cfg(feature = "metrics")]
+            area_number,
+        )
+    }
 
-        // Generate hash salts
-        let salts = (0..hash_number)
-            .into_par_iter()
-            .map(|_| {
-                (0..max_input_size)
-                    .into_par_iter()
-                    .map(|_| rng.lock().unwrap().gen())
-                    .collect::<Salt>()
-            })
-            .collect::<Vec<Salt>>();
+    /// Constructor of the SBF data structure with salts drawn from the given RNG
+    ///
+    /// The salts are drawn in order, one salt at a time, so a seeded RNG produces deterministic
+    /// salts.
+    pub fn new_with_rng<R: RngCore>(
+        cells: usize,
+        hash_number: usize,
+        max_input_size: usize,
+        hash_function: H,
+        rng: &mut R,
+        #[cfg(feature = "metrics")] area_number: U,
+    ) -> Result<Self, Error> {
+        Self::with_salts(
+            cells,
+            Salts::generate(rng, hash_number, max_input_size),
+            hash_function,
+            #[cfg(feature = "metrics")]
+            area_number,
+        )
+    }
 
-        Ok(SBF {
-            filter: vec![U::zero(); cells.to_usize().ok_or(Error::IndexOutOfBounds)?],
+    /// Constructor of the SBF data structure with salts derived from a master key
+    ///
+    /// The salts are not stored, each of them is derived when needed from `key` and its index.
+    /// This keeps the filter (and its serialized form) compact for large `max_input_size`, at the
+    /// cost of deriving the salts on every insertion and check.
+    pub fn new_with_key(
+        cells: usize,
+        hash_number: usize,
+        max_input_size: usize,
+        hash_function: H,
+        key: SaltKey,
+        #[cfg(feature = "metrics")] area_number: U,
+    ) -> Result<Self, Error> {
+        Self::with_salts(
+            cells,
+            Salts::Derived {
+                key,
+                hash_number,
+                max_input_size,
+            },
             hash_function,
+            #[cfg(feature = "metrics")]
+            area_number,
+        )
+    }
+
+    /// Builds an empty filter with the given salts
+    fn with_salts(
+        cells: usize,
+        salts: Salts,
+        hash_function: H,
+        #[cfg(feature = "metrics")] area_number: U,
+    ) -> Result<Self, Error> {
+        if cells == 0 {
+            return Err(Error::ZeroCells);
+        }
+        if salts.is_empty() {
+            return Err(Error::ZeroHashes);
+        }
+
+        #[cfg(feature = "metrics")]
+        let area_number = area_number.to_usize().ok_or(Error::TooManyAreas)?;
+        #[cfg(not(feature = "metrics"))]
+        let area_number = Self::max_area_number();
+
+        Ok(Self::from_parts(
+            cells,
             salts,
+            hash_function,
+            area_number,
+            cfg!(feature = "metrics"),
+        ))
+    }
 
+    /// Builds an empty filter from already validated parameters
+    pub(crate) fn from_parts(
+        cells: usize,
+        salts: Salts,
+        hash_function: H,
+        area_number: usize,
+        metrics_enabled: bool,
+    ) -> Self {
+        Self::from_cells(
+            Cells::new(cells),
+            salts,
+            hash_function,
+            area_number,
+            metrics_enabled,
+        )
+    }
+
+    /// Builds a filter holding the given cells from already validated parameters
+    ///
+    /// The metrics, if enabled, start empty.
+    pub(crate) fn from_cells(
+        filter: Cells<U>,
+        salts: Salts,
+        hash_function: H,
+        area_number: usize,
+        #[cfg_attr(not(feature = "metrics"), allow(unused_variables))] metrics_enabled: bool,
+    ) -> Self {
+        SBF {
             #[cfg(feature = "metrics")]
-            metrics: Metrics {
-                cells: cells.to_usize().unwrap(),
-                hash_number,
-                members: 0,
-                collisions: 0,
-                safeness: 0.0,
-                area_number: area_number.to_usize().unwrap(),
-                area_members: vec![0; area_number.to_usize().ok_or(Error::IndexOutOfBounds)?],
-                area_cells: vec![0; area_number.to_usize().ok_or(Error::IndexOutOfBounds)?],
-                area_expected_cells: vec![
-                    -1;
-                    area_number.to_usize().ok_or(Error::IndexOutOfBounds)?
-                ],
-                area_self_collisions: vec![
-                    0;
-                    area_number.to_usize().ok_or(Error::IndexOutOfBounds)?
-                ],
-                area_fpp: vec![-1.0; area_number.to_usize().ok_or(Error::IndexOutOfBounds)?],
-                area_isep: vec![-1.0; area_number.to_usize().ok_or(Error::IndexOutOfBounds)?],
-                area_prior_fpp: vec![-1.0; area_number.to_usize().ok_or(Error::IndexOutOfBounds)?],
-                area_prior_isep: vec![-1.0; area_number.to_usize().ok_or(Error::IndexOutOfBounds)?],
-                area_prior_safep: vec![
-                    -1.0;
-                    area_number.to_usize().ok_or(Error::IndexOutOfBounds)?
-                ],
-            },
-        })
+            metrics: Metrics::new(
+                filter.len(),
+                salts.len(),
+                if metrics_enabled { area_number } else { 0 },
+            ),
+            #[cfg(feature = "metrics")]
+            metrics_enabled,
+
+            filter,
+            hash_function,
+            index_mode: IndexMode::default(),
+            input_mode: InputMode::default(),
+            area_number,
+            salts,
+        }
+    }
+
+    /// Returns the largest number of areas (including the empty area `0`) that fits in `U`
+    pub(crate) fn max_area_number() -> usize {
+        max_area_number::<U>()
+    }
+
+    /// Returns the number of areas, including the empty area `0`
+    ///
+    /// The number is clamped to the areas that fit in `U`, in case a deserialized filter stores a
+    /// larger one.
+    pub fn area_number(&self) -> usize {
+        self.area_number.min(Self::max_area_number())
+    }
+
+    /// Returns a builder of SBF data structures
+    pub fn builder() -> SbfBuilder<U, H> {
+        SbfBuilder::default()
     }
 
     /// Constructor of the SBF data structure using optimal parameters
@@ -223,15 +492,19 @@ where
         expected_inserts: usize,
         max_fpp: f64,
         max_input_size: usize,
-        hash_function: HashFunction,
+        hash_function: H,
         #[cfg(feature = "metrics")] area_number: U,
     ) -> Result<Self, Error> {
+        if expected_inserts == 0 || !(max_fpp > 0.0 && max_fpp < 1.0) {
+            return Err(Error::InvalidParameters);
+        }
+
         let optimal_cells =
-            (-(expected_inserts as f64) * max_fpp.ln() / (2.0f64.ln().powi(2))) as u64;
+            (-(expected_inserts as f64) * max_fpp.ln() / (2.0f64.ln().powi(2))) as usize;
         let hash_number =
             (optimal_cells as f64 / (expected_inserts as f64) * 2.0f64.ln()).ceil() as usize;
         Self::new(
-            U::from_u64(optimal_cells).ok_or(Error::IndexOutOfBounds)?,
+            optimal_cells,
             hash_number,
             max_input_size,
             hash_function,
@@ -245,26 +518,320 @@ where
     ///
     /// Because of the probabilistic nature of this data structure, it is possible for it to return
     /// a false positive.
-    pub fn check(&self, content: Vec<u8>) -> Result<&U, Error> {
-        self.calc_indexes(content)
-            .par_iter()
-            .map(|i| self.get_cell(*i))
-            .try_reduce_with(|a, b| Ok(a.min(b)))
-            .expect("Some value, since the iterator is not empty")
+    pub fn check(&self, content: impl AsRef<[u8]>) -> Result<&U, Error> {
+        self.min_cell(&self.calc_indexes(content.as_ref())?)
     }
 
-    /// Insert the content in the filter associated to the given area.
-    pub fn insert(&mut self, content: Vec<u8>, area: U) -> Result<(), Error> {
-        self.calc_indexes(content)
+    /// Query an input for presence in the filter.
+    ///
+    /// Differently from [`check`](SBF::check), it distinguishes inputs whose cells all hold the
+    /// same area from inputs whose cells hold different areas, which hints at an inter-set error.
+    pub fn query(&self, content: impl AsRef<[u8]>) -> Result<Membership<U>, Error> {
+        let mut values = self
+            .calc_indexes(content.as_ref())?
             .iter()
-            .try_for_each(|i| self.set_cell(*i, area).map(|_| ()))
-            .map(|_| {
-                #[cfg(feature = "metrics")]
-                #[allow(clippy::integer_arithmetic)]
-                {
-                    self.metrics.members += 1;
-                    self.metrics.area_members[area.to_usize().unwrap()] += 1;
-                };
+            .map(|i| self.get_cell(*i).copied())
+            .collect::<Result<Vec<U>, Error>>()?;
+        values.sort_unstable();
+        values.dedup();
+
+        match values.as_slice() {
+            [] => Err(Error::EmptyHashSet),
+            [first, ..] if first.is_zero() => Ok(Membership::Absent),
+            [area] => Ok(Membership::Member { area: *area }),
+            _ => Ok(Membership::Ambiguous { candidates: values }),
+        }
+    }
+
+    /// Explain the answer of the filter to a query.
+    ///
+    /// Returns the cell probed by each hash of the input, the value stored in it and which of the
+    /// probes determined the answer of [`check`](SBF::check).
+    /// Useful to debug collisions and inter-set errors.
+    pub fn explain(&self, content: impl AsRef<[u8]>) -> Result<Explanation<U>, Error> {
+        let probes = self
+            .calc_indexes(content.as_ref())?
+            .into_iter()
+            .enumerate()
+            .map(|(hash_index, cell_index)| {
+                Ok(Probe {
+                    hash_index,
+                    cell_index,
+                    value: *self.get_cell(cell_index)?,
+                })
             })
+            .collect::<Result<Vec<Probe<U>>, Error>>()?;
+
+        let minimum = probes
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, probe)| probe.value)
+            .map(|(i, _)| i)
+            .ok_or(Error::EmptyHashSet)?;
+
+        Ok(Explanation { probes, minimum })
+    }
+
+    /// Returns the lowest value among the cells
+    fn min_cell(&self, indexes: &[usize]) -> Result<&U, Error> {
+        lowest_cell(indexes, |i| self.get_cell(i))
+    }
+
+    /// Insert the content in the filter associated to the given area.
+    ///
+    /// The area `0` is reserved to mark the absence of an element and areas must be lower than the
+    /// number of areas of the filter.
+    pub fn insert(&mut self, content: impl AsRef<[u8]>, area: U) -> Result<(), Error> {
+        let area_index = self.area_index(area)?;
+        let indexes = self.calc_indexes(content.as_ref())?;
+        self.set_cells(&indexes, area, area_index)
     }
+
+    /// Validates the area, returning it as an index
+    pub(crate) fn area_index(&self, area: U) -> Result<usize, Error> {
+        if area == U::zero() {
+            return Err(Error::ReservedAreaZero);
+        }
+        match area.to_usize() {
+            Some(area_index) if area_index < self.area_number() => Ok(area_index),
+            _ => Err(Error::AreaOutOfRange),
+        }
+    }
+
+    /// Marks the cells of a single element with the given area
+    pub(crate) fn set_cells(
+        &mut self,
+        indexes: &[usize],
+        area: U,
+        area_index: usize,
+    ) -> Result<(), Error> {
+        Marks {
+            filter: &mut self.filter,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics_enabled.then_some(&mut self.metrics),
+        }
+        .set_cells(indexes, area, area_index)
+    }
+
+    /// Check a batch of inputs for presence in the filter.
+    ///
+    /// The inputs are checked in parallel, the results are in the same order of the inputs.
+    pub fn check_batch<K>(&self, contents: &[K]) -> Result<Vec<U>, Error>
+    where
+        K: AsRef<[u8]> + Sync,
+    {
+        contents
+            .par_iter()
+            .map(|content| self.check(content).copied())
+            .collect()
+    }
+
+    /// Insert a batch of inputs in the filter, each associated to its area.
+    ///
+    /// The inputs are taken in chunks of [`BATCH_CHUNK`] elements, the cell indexes of a chunk are
+    /// computed in parallel, then its cells are updated in the order of the inputs, so the filter
+    /// ends up exactly as after inserting the inputs one by one.
+    /// The insertion stops at the first invalid input, the inputs before it stay inserted.
+    pub fn insert_batch<I, K>(&mut self, contents: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (K, U)>,
+        K: AsRef<[u8]> + Send,
+    {
+        let mut contents = contents.into_iter();
+        let mut chunk = Vec::with_capacity(BATCH_CHUNK);
+        loop {
+            chunk.extend(contents.by_ref().take(BATCH_CHUNK));
+            if chunk.is_empty() {
+                return Ok(());
+            }
+
+            let indexes = chunk
+                .par_drain(..)
+                .map(|(content, area)| {
+                    let area_index = self.area_index(area)?;
+                    Ok((self.calc_indexes(content.as_ref())?, area, area_index))
+                })
+                .collect::<Vec<Result<_, Error>>>();
+
+            indexes.into_iter().try_for_each(|indexes| {
+                let (indexes, area, area_index) = indexes?;
+                self.set_cells(&indexes, area, area_index)
+            })?;
+        }
+    }
+
+    /// Insert a parallel batch of inputs in the filter, each associated to its area.
+    ///
+    /// Every worker buffers the cell indexes of at most [`BATCH_CHUNK`] inputs before updating
+    /// the cells, in no particular order. The filter ends up as after inserting the inputs one by
+    /// one, as each cell keeps the highest area inserted in it.
+    /// If an input is invalid an error is returned and only part of the batch may be inserted.
+    pub fn par_insert_batch<I, K>(&mut self, contents: I) -> Result<(), Error>
+    where
+        I: IntoParallelIterator<Item = (K, U)>,
+        K: AsRef<[u8]> + Send,
+    {
+        // The cells are moved out of the filter, so that its parameters can be shared by the
+        // workers while the cells are updated
+        let cells = self.filter.len();
+        let mut filter = std::mem::take(&mut self.filter);
+        #[cfg(feature = "metrics")]
+        let mut metrics = std::mem::replace(&mut self.metrics, Metrics::new(0, 0, 0));
+
+        let marks = Mutex::new(Marks {
+            filter: &mut filter,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics_enabled.then_some(&mut metrics),
+        });
+        let apply = |chunk: &mut Vec<(Vec<usize>, U, usize)>| {
+            let mut marks = marks.lock().unwrap_or_else(PoisonError::into_inner);
+            chunk.drain(..).try_for_each(|(indexes, area, area_index)| {
+                marks.set_cells(&indexes, area, area_index)
+            })
+        };
+        let result = contents
+            .into_par_iter()
+            .try_fold(Vec::new, |mut chunk, (content, area)| {
+                let area_index = self.area_index(area)?;
+                chunk.push((
+                    self.calc_indexes_within(content.as_ref(), cells)?,
+                    area,
+                    area_index,
+                ));
+                if chunk.len() >= BATCH_CHUNK {
+                    apply(&mut chunk)?;
+                }
+                Ok(chunk)
+            })
+            .try_for_each(|chunk| apply(&mut chunk?));
+
+        self.filter = filter;
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics = metrics;
+        }
+        result
+    }
+
+    /// Check an encodable value for presence in the filter.
+    ///
+    /// The value is encoded with [`stable_encode`] and checked as in [`check`](SBF::check).
+    pub fn check_encoded<T: StableEncode + ?Sized>(&self, value: &T) -> Result<&U, Error> {
+        self.check(stable_encode(value))
+    }
+
+    /// Insert an encodable value in the filter associated to the given area.
+    ///
+    /// The value is encoded with [`stable_encode`] and inserted as in [`insert`](SBF::insert).
+    pub fn insert_encoded<T: StableEncode + ?Sized>(
+        &mut self,
+        value: &T,
+        area: U,
+    ) -> Result<(), Error> {
+        self.insert(stable_encode(value), area)
+    }
+}
+
+/// Cells of a filter and their metrics, updated apart from the filter parameters
+struct Marks<'a, U> {
+    filter: &'a mut [U],
+    #[cfg(feature = "metrics")]
+    metrics: Option<&'a mut Metrics>,
+}
+
+impl<U: Area> Marks<'_, U> {
+    /// Marks the cells of a single element with the given area
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn set_cells(&mut self, indexes: &[usize], area: U, area_index: usize) -> Result<(), Error> {
+        indexes.iter().try_for_each(|i| self.set_cell(*i, area))?;
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.as_deref_mut() {
+            metrics.record_member(area_index);
+        }
+        Ok(())
+    }
+
+    /// Sets the content of the cell if the input area is higher than the one in the filter
+    fn set_cell(&mut self, index: usize, area: U) -> Result<(), Error> {
+        let v = self.filter.get_mut(index).ok_or(Error::IndexOutOfBounds)?;
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.as_deref_mut() {
+            metrics.record_cell(
+                v.to_usize().unwrap_or_default(),
+                area.to_usize().unwrap_or_default(),
+            );
+        }
+
+        if *v == U::zero() || *v < area {
+            // Cell is not marked or cell has lower value than the input area
+            *v = area;
+        } else if *v >= area {
+            // Cell hash same or higher value than input area
+        }
+        Ok(())
+    }
+}
+
+/// Returns the largest number of areas (including the empty area `0`) that fits in `U`
+///
+/// Also the number of areas of filters serialized without it, which accept any area.
+fn max_area_number<U: Bounded + ToPrimitive>() -> usize {
+    U::max_value()
+        .to_usize()
+        .and_then(|max| max.checked_add(1))
+        .unwrap_or(usize::MAX)
+}
+
+/// Returns the lowest value among the cells, each read with `get`
+///
+/// Returns [`Error::EmptyHashSet`] if there are no indexes.
+pub(crate) fn lowest_cell<T, F>(indexes: &[usize], mut get: F) -> Result<T, Error>
+where
+    T: Ord,
+    F: FnMut(usize) -> Result<T, Error>,
+{
+    indexes
+        .iter()
+        .try_fold(None, |min: Option<T>, i| {
+            let v = get(*i)?;
+            Ok(Some(match min {
+                Some(min) => min.min(v),
+                None => v,
+            }))
+        })?
+        .ok_or(Error::EmptyHashSet)
+}
+
+/// Reads a `u64` from the digest at the given offset, if the digest is long enough
+fn read_digest<B: ByteOrder>(digest: &[u8], offset: usize) -> Option<u64> {
+    digest.get(offset..offset + 8).map(B::read_u64)
+}
+
+/// Enhanced double hashing of the base hashes `h1` and `h2` over `cells` cells
+///
+/// The first index is `h1` and the strides between the following ones are `h2`, `h2 + 1`,
+/// `h2 + 3`, `h2 + 6` and so on, all reduced modulo the number of cells at each step so that no
+/// intermediate value wraps around.
+fn double_hashing(h1: u64, h2: u64, cells: u64) -> impl Iterator<Item = u64> {
+    let (mut x, mut y) = (h1 % cells, h2 % cells);
+    (1..).map(move |i| {
+        let index = x;
+        x = add_mod(x, y, cells);
+        y = add_mod(y, i % cells, cells);
+        index
+    })
+}
+
+/// Sum modulo `m` of two values already reduced modulo `m`
+fn add_mod(a: u64, b: u64, m: u64) -> u64 {
+    match a.checked_sub(m - b) {
+        Some(sum) => sum,
+        None => a + b,
+    }
+}
+
+/// Metrics of deserialized filters are enabled unless stated otherwise
+#[cfg(all(feature = "metrics", feature = "serde_support"))]
+fn metrics_enabled_default() -> bool {
+    true
 }
@@ -11,4 +11,61 @@ pub enum Error {
     /// Access index is larger than the maximum size allowed
     #[error("Index out of bounds")]
     IndexOutOfBounds,
+    /// Input is larger than the maximum input size of the filter
+    #[error("Input too long")]
+    InputTooLong,
+    /// The filter must have at least one cell
+    #[error("Zero cells")]
+    ZeroCells,
+    /// The filter must use at least one hash function
+    #[error("Zero hashes")]
+    ZeroHashes,
+    /// The maximum input size must be positive
+    #[error("Zero input size")]
+    ZeroInputSize,
+    /// No hash function has been selected
+    #[error("Missing hash function")]
+    MissingHashFunction,
+    /// The filter must have at least one area besides the empty area `0`
+    #[error("Not enough areas")]
+    NotEnoughAreas,
+    /// The areas cannot be represented by the area type of the filter
+    #[error("Too many areas for the area type")]
+    TooManyAreas,
+    /// The number of areas is required to track the metrics
+    #[error("Missing area number")]
+    MissingAreaNumber,
+    /// Metrics require the `metrics` feature
+    #[error("Metrics unavailable")]
+    MetricsUnavailable,
+    /// The parameters are outside of their valid domain
+    #[error("Invalid parameters")]
+    InvalidParameters,
+    /// The area is not lower than the number of areas of the filter
+    #[error("Area out of range")]
+    AreaOutOfRange,
+    /// The area `0` is reserved to mark the absence of an element
+    #[error("Area 0 is reserved")]
+    ReservedAreaZero,
+    /// The filter has no hash functions to compute the cell indexes
+    #[error("Empty hash set")]
+    EmptyHashSet,
+    /// The digest of the hash function is shorter than 8 bytes
+    #[error("Digest too short")]
+    DigestTooShort,
+    /// The filters do not share the same parameters and salts
+    #[error("Incompatible filters")]
+    IncompatibleFilters,
+    /// The filter data is inconsistent or corrupted
+    #[error("Corrupt data")]
+    CorruptData,
+    /// A counter of the filter reached its maximum value
+    #[error("Counter overflow")]
+    CounterOverflow,
+    /// The element to remove has not been inserted in the given area
+    #[error("Element not present")]
+    NotPresent,
+    /// The data was written by a newer version of the crate
+    #[error("Unsupported version")]
+    UnsupportedVersion,
 }
@@ -0,0 +1,160 @@
+//! Generational SBF data structure
+//!
+//! [`GenerationalSBF`] keeps a ring of filters, one for each generation: elements are inserted
+//! in the newest generation, and when the generation ends a new one is started and the oldest
+//! one is dropped, so elements expire after a fixed number of generations.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::{
+    builder::SbfBuilder,
+    data_structure::SBF,
+    error::Error,
+    hasher::SbfHasher,
+    types::{Area, HashFunction},
+};
+
+/// Boundary between two generations
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rotation {
+    /// A generation ends after the given number of insertions
+    Inserts(usize),
+    /// A generation ends after the given time interval
+    Interval(Duration),
+}
+
+/// Spatial Bloom Filter whose elements expire after a number of generations
+///
+/// Each generation is a filter built by the given [`SbfBuilder`].
+/// Checks look through the active generations and return the highest area found, giving a
+/// sliding window membership.
+/// With [`Rotation::Interval`] a generation expires as soon as its time is over, even if no
+/// element is inserted to trigger the rotation.
+#[derive(Clone, Debug)]
+pub struct GenerationalSBF<U, H = HashFunction>
+where
+    U: Area,
+{
+    /// Builder of the generations
+    builder: SbfBuilder<U, H>,
+    /// Generations, from the oldest to the newest
+    generations: VecDeque<SBF<U, H>>,
+    /// Maximum number of generations
+    generation_number: usize,
+    /// Boundary between two generations
+    rotation: Rotation,
+    /// Number of insertions in the newest generation
+    inserts: usize,
+    /// Start of the newest generation
+    started: Instant,
+}
+
+impl<U, H> GenerationalSBF<U, H>
+where
+    H: SbfHasher + Clone,
+    U: Area,
+{
+    /// Builds a generational filter keeping up to `generation_number` generations
+    ///
+    /// Returns [`Error::InvalidParameters`] if the number of generations, the number of
+    /// insertions or the interval of the rotation are zero.
+    pub fn new(
+        builder: SbfBuilder<U, H>,
+        generation_number: usize,
+        rotation: Rotation,
+    ) -> Result<Self, Error> {
+        let empty_rotation = match rotation {
+            Rotation::Inserts(inserts) => inserts == 0,
+            Rotation::Interval(interval) => interval.is_zero(),
+        };
+        if generation_number == 0 || empty_rotation {
+            return Err(Error::InvalidParameters);
+        }
+
+        let first = builder.clone().build()?;
+        Ok(GenerationalSBF {
+            builder,
+            generations: VecDeque::from(vec![first]),
+            generation_number,
+            rotation,
+            inserts: 0,
+            started: Instant::now(),
+        })
+    }
+
+    /// Returns the active generations, from the oldest to the newest
+    pub fn generations(&self) -> impl Iterator<Item = &SBF<U, H>> {
+        self.generations.iter().skip(self.expired_generations())
+    }
+
+    /// Check an input for presence in the filter.
+    ///
+    /// Returns `0` if the input has not been inserted in the active generations, or the highest
+    /// area found in them, see [`SBF::check`].
+    pub fn check(&self, content: impl AsRef<[u8]>) -> Result<U, Error> {
+        let content = content.as_ref();
+        self.generations().try_fold(U::zero(), |max, generation| {
+            generation.check(content).map(|area| max.max(*area))
+        })
+    }
+
+    /// Insert the content in the newest generation associated to the given area, starting a new
+    /// generation first if the newest one is over.
+    pub fn insert(&mut self, content: impl AsRef<[u8]>, area: U) -> Result<(), Error> {
+        match self.rotation {
+            Rotation::Inserts(inserts) if self.inserts >= inserts => self.rotate()?,
+            Rotation::Inserts(_) => {}
+            Rotation::Interval(interval) => {
+                // Catch up with the generations ended since the last insertion
+                let ended = self.ended_intervals(interval);
+                for _ in 0..ended.min(self.generation_number) {
+                    self.rotate()?;
+                }
+            }
+        }
+
+        self.generations
+            .back_mut()
+            .ok_or(Error::CorruptData)?
+            .insert(content, area)?;
+        self.inserts = self.inserts.saturating_add(1);
+        Ok(())
+    }
+
+    /// Starts a new generation, dropping the oldest one if the maximum number of generations is
+    /// reached.
+    pub fn rotate(&mut self) -> Result<(), Error> {
+        let generation = self.builder.clone().build()?;
+        if self.generations.len() >= self.generation_number {
+            self.generations.pop_front();
+        }
+        self.generations.push_back(generation);
+        self.inserts = 0;
+        self.started = Instant::now();
+        Ok(())
+    }
+
+    /// Number of whole intervals elapsed since the start of the newest generation
+    fn ended_intervals(&self, interval: Duration) -> usize {
+        (self.started.elapsed().as_nanos() / interval.as_nanos().max(1))
+            .try_into()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Number of the oldest generations whose time is over but that have not been rotated out
+    /// yet
+    fn expired_generations(&self) -> usize {
+        match self.rotation {
+            Rotation::Inserts(_) => 0,
+            Rotation::Interval(interval) => {
+                // After `ended` intervals, only the newest `generation_number - ended` generations
+                // would survive the rotations
+                let surviving = self
+                    .generation_number
+                    .saturating_sub(self.ended_intervals(interval));
+                self.generations.len().saturating_sub(surviving)
+            }
+        }
+    }
+}
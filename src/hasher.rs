@@ -0,0 +1,244 @@
+//! Hash function adapters used to compute the cell indexes
+
+#[cfg(feature = "siphash_hash")]
+use std::hash::Hasher;
+
+#[cfg(feature = "siphash_hash")]
+use siphasher::sip128::Hasher128;
+
+use crate::types::HashFunction;
+
+/// Hash function used by the `SBF` to compute the cell indexes of an input
+///
+/// It is implemented by [`HashFunction`] for the built-in hash functions, but can be implemented
+/// by any custom type to plug a different hash function into the filter.
+pub trait SbfHasher: Send + Sync {
+    /// Computes the digest of the input buffer
+    ///
+    /// The digest must be at least 8 bytes long.
+    fn digest(&self, buff: &[u8]) -> Vec<u8>;
+
+    /// Computes the digest of the concatenation of the input buffers
+    ///
+    /// By default the buffers are concatenated and passed to [`digest`](SbfHasher::digest),
+    /// hash functions that support it can override it to stream the buffers instead.
+    fn digest_parts(&self, parts: &[&[u8]]) -> Vec<u8> {
+        self.digest(&parts.concat())
+    }
+}
+
+impl SbfHasher for HashFunction {
+    fn digest(&self, buff: &[u8]) -> Vec<u8> {
+        match self {
+            #[cfg(feature = "md5_hash")]
+            HashFunction::MD5 => md5::compute(buff).to_vec(),
+            #[cfg(feature = "md4_hash")]
+            HashFunction::MD4 => <md4::Md4 as md4::Digest>::digest(buff).to_vec(),
+            #[cfg(feature = "sha1_hash")]
+            HashFunction::SHA1 => <sha1::Sha1 as sha1::Digest>::digest(buff).to_vec(),
+            #[cfg(feature = "sha256_hash")]
+            HashFunction::SHA256 => <sha2::Sha256 as sha2::Digest>::digest(buff).to_vec(),
+            #[cfg(feature = "blake3_hash")]
+            HashFunction::BLAKE3 => blake3::hash(buff).as_bytes().to_vec(),
+            #[cfg(feature = "xxh3_hash")]
+            HashFunction::XXH3 => xxhash_rust::xxh3::xxh3_128(buff).to_le_bytes().to_vec(),
+            #[cfg(feature = "siphash_hash")]
+            HashFunction::SipHash => {
+                let mut hasher = siphasher::sip128::SipHasher13::new();
+                hasher.write(buff);
+                hasher.finish128().as_bytes().to_vec()
+            }
+        }
+    }
+
+    fn digest_parts(&self, parts: &[&[u8]]) -> Vec<u8> {
+        match self {
+            #[cfg(feature = "md5_hash")]
+            HashFunction::MD5 => {
+                let mut context = md5::Context::new();
+                parts.iter().for_each(|part| context.consume(part));
+                context.compute().to_vec()
+            }
+            #[cfg(feature = "md4_hash")]
+            HashFunction::MD4 => {
+                let mut hasher = <md4::Md4 as md4::Digest>::new();
+                parts
+                    .iter()
+                    .for_each(|part| md4::Digest::update(&mut hasher, part));
+                md4::Digest::finalize(hasher).to_vec()
+            }
+            #[cfg(feature = "sha1_hash")]
+            HashFunction::SHA1 => {
+                let mut hasher = <sha1::Sha1 as sha1::Digest>::new();
+                parts
+                    .iter()
+                    .for_each(|part| sha1::Digest::update(&mut hasher, part));
+                sha1::Digest::finalize(hasher).to_vec()
+            }
+            #[cfg(feature = "sha256_hash")]
+            HashFunction::SHA256 => {
+                let mut hasher = <sha2::Sha256 as sha2::Digest>::new();
+                parts
+                    .iter()
+                    .for_each(|part| sha2::Digest::update(&mut hasher, part));
+                sha2::Digest::finalize(hasher).to_vec()
+            }
+            #[cfg(feature = "blake3_hash")]
+            HashFunction::BLAKE3 => {
+                let mut hasher = blake3::Hasher::new();
+                parts.iter().for_each(|part| {
+                    hasher.update(part);
+                });
+                hasher.finalize().as_bytes().to_vec()
+            }
+            #[cfg(feature = "xxh3_hash")]
+            HashFunction::XXH3 => {
+                let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+                parts.iter().for_each(|part| hasher.update(part));
+                hasher.digest128().to_le_bytes().to_vec()
+            }
+            #[cfg(feature = "siphash_hash")]
+            HashFunction::SipHash => {
+                let mut hasher = siphasher::sip128::SipHasher13::new();
+                parts.iter().for_each(|part| hasher.write(part));
+                hasher.finish128().as_bytes().to_vec()
+            }
+        }
+    }
+}
+
+/// Value with an explicit byte encoding, independent of the platform and of the Rust version
+///
+/// The encoding is defined by this crate and never changes, so a filter persisted with
+/// [`SBF::insert_encoded`](crate::SBF::insert_encoded) keeps matching its values:
+/// - integers are written in little endian, `usize` and `isize` widened to 64 bits,
+/// - `bool` is written as a `u8` and `char` as a `u32`,
+/// - strings are written as their UTF-8 bytes followed by `0xff`,
+/// - slices, arrays and vectors are written as their `u64` length followed by their elements,
+/// - tuples are written as their elements one after the other.
+///
+/// Values are not accepted through [`std::hash::Hash`]: the bytes it feeds to a hasher are not
+/// guaranteed to be the same across platforms and Rust versions, so a persisted filter could stop
+/// matching its values.
+/// Other types implement the trait by encoding their fields one after the other:
+///
+/// ```
+/// use sbf::hasher::{stable_encode, StableEncode};
+///
+/// struct Point {
+///     x: i32,
+///     y: i32,
+/// }
+///
+/// impl StableEncode for Point {
+///     fn stable_encode(&self, buffer: &mut Vec<u8>) {
+///         self.x.stable_encode(buffer);
+///         self.y.stable_encode(buffer);
+///     }
+/// }
+///
+/// assert_eq!(stable_encode(&(1i32, 2i32)), stable_encode(&Point { x: 1, y: 2 }));
+/// ```
+pub trait StableEncode {
+    /// Appends the encoding of the value to the buffer
+    fn stable_encode(&self, buffer: &mut Vec<u8>);
+}
+
+/// Encodes a value as a platform independent sequence of bytes, see [`StableEncode`]
+pub fn stable_encode<T: StableEncode + ?Sized>(value: &T) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    value.stable_encode(&mut buffer);
+    buffer
+}
+
+/// Implements [`StableEncode`] for integers, widened to the given type
+macro_rules! stable_encode_int {
+    ($($t:ty => $wide:ty),* $(,)?) => {
+        $(
+            impl StableEncode for $t {
+                fn stable_encode(&self, buffer: &mut Vec<u8>) {
+                    buffer.extend_from_slice(&(*self as $wide).to_le_bytes())
+                }
+            }
+        )*
+    };
+}
+
+stable_encode_int!(
+    u8 => u8, u16 => u16, u32 => u32, u64 => u64, u128 => u128, usize => u64,
+    i8 => i8, i16 => i16, i32 => i32, i64 => i64, i128 => i128, isize => i64,
+);
+
+impl StableEncode for bool {
+    fn stable_encode(&self, buffer: &mut Vec<u8>) {
+        (*self as u8).stable_encode(buffer)
+    }
+}
+
+impl StableEncode for char {
+    fn stable_encode(&self, buffer: &mut Vec<u8>) {
+        (*self as u32).stable_encode(buffer)
+    }
+}
+
+impl StableEncode for str {
+    fn stable_encode(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(self.as_bytes());
+        buffer.push(0xff)
+    }
+}
+
+impl StableEncode for String {
+    fn stable_encode(&self, buffer: &mut Vec<u8>) {
+        self.as_str().stable_encode(buffer)
+    }
+}
+
+impl<T: StableEncode> StableEncode for [T] {
+    fn stable_encode(&self, buffer: &mut Vec<u8>) {
+        self.len().stable_encode(buffer);
+        self.iter().for_each(|v| v.stable_encode(buffer))
+    }
+}
+
+impl<T: StableEncode, const N: usize> StableEncode for [T; N] {
+    fn stable_encode(&self, buffer: &mut Vec<u8>) {
+        self.as_slice().stable_encode(buffer)
+    }
+}
+
+impl<T: StableEncode> StableEncode for Vec<T> {
+    fn stable_encode(&self, buffer: &mut Vec<u8>) {
+        self.as_slice().stable_encode(buffer)
+    }
+}
+
+impl<T: StableEncode + ?Sized> StableEncode for &T {
+    fn stable_encode(&self, buffer: &mut Vec<u8>) {
+        (**self).stable_encode(buffer)
+    }
+}
+
+/// Implements [`StableEncode`] for tuples, encoding their elements in order
+macro_rules! stable_encode_tuple {
+    ($(($($name:ident),+)),* $(,)?) => {
+        $(
+            impl<$($name: StableEncode),+> StableEncode for ($($name,)+) {
+                #[allow(non_snake_case)]
+                fn stable_encode(&self, buffer: &mut Vec<u8>) {
+                    let ($($name,)+) = self;
+                    $($name.stable_encode(buffer);)+
+                }
+            }
+        )*
+    };
+}
+
+stable_encode_tuple!(
+    (A),
+    (A, B),
+    (A, B, C),
+    (A, B, C, D),
+    (A, B, C, D, E),
+    (A, B, C, D, E, F),
+);
@@ -15,18 +15,43 @@ unreachable_pub,
 clippy::all,
 )]
 
-#[cfg(feature = "metrics")]
-pub use metrics::Metrics;
+#[cfg(feature = "mmap")]
+pub use mapped::MappedSBF;
 pub use {
+    builder::SbfBuilder,
+    collect::{SbfCollect, SbfParCollect},
+    concurrent::{AtomicArea, ConcurrentSBF},
+    counting::CountingSBF,
     data_structure::SBF,
     error::Error,
-    types::{HashFunction, Salt},
+    generational::{GenerationalSBF, Rotation},
+    hasher::{SbfHasher, StableEncode},
+    metrics::{Metrics, Plan, Planner},
+    packed::PackedSBF,
+    scalable::ScalableSBF,
+    types::{
+        Area, CellDiff, Explanation, HashFunction, IndexMode, InputMode, Membership, Probe, Salt,
+        SaltKey, Salts, CACHE_LINE_BYTES,
+    },
 };
 
+pub mod binary;
+pub mod builder;
+mod cells;
+pub mod collect;
+pub mod concurrent;
+pub mod counting;
 pub mod data_structure;
 pub mod error;
-#[cfg(feature = "metrics")]
+pub mod generational;
+pub mod hasher;
+pub mod libsbf;
+#[cfg(feature = "mmap")]
+pub mod mapped;
 pub mod metrics;
+pub mod operations;
+pub mod packed;
+pub mod scalable;
 pub mod types;
 
 #[cfg(test)]
@@ -0,0 +1,200 @@
+//! Filters following the original [libSBF-cpp](https://github.com/spatialbloomfilter/libSBF-cpp)
+//! library
+//!
+//! libSBF-cpp filters have `2^bit_mapping` cells and combine each input with the salts and the
+//! hash function as described by [`InputMode::LibSbf`] and [`IndexMode::LibSbf`]:
+//! - the salts are [`MAX_INPUT_SIZE`] bytes long and are stored in the salt file one after the
+//!   other, without any header,
+//! - the hash functions are identified by their hash family, `1` for SHA-1, `4` for MD4 and `5`
+//!   for MD5,
+//! - the cells are saved as text, one `index;value` line for each cell.
+//!
+//! The layouts and the index derivation follow the published description of the library, but
+//! they have not been verified against salts, indexes or cells produced by libSBF-cpp itself, so
+//! exchanging filters with it is not guaranteed to work.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+use crate::{
+    binary::invalid,
+    cells::Cells,
+    data_structure::SBF,
+    error::Error,
+    types::{Area, HashFunction, IndexMode, InputMode, Salts},
+};
+
+/// Length of the salts, and maximum input size, of libSBF-cpp filters
+pub const MAX_INPUT_SIZE: usize = 128;
+
+/// Largest `bit_mapping` supported by libSBF-cpp
+pub const MAX_BIT_MAPPING: u32 = 32;
+
+/// Cells written by [`SBF::write_libsbf_cells`]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CellsMode {
+    /// Every cell of the filter
+    #[default]
+    All,
+    /// Only the cells holding an area
+    NonEmpty,
+}
+
+/// Returns the hash function of a libSBF-cpp hash family, if its feature is enabled
+pub fn hash_function(hash_family: u32) -> Option<HashFunction> {
+    match hash_family {
+        #[cfg(feature = "sha1_hash")]
+        1 => Some(HashFunction::SHA1),
+        #[cfg(feature = "md4_hash")]
+        4 => Some(HashFunction::MD4),
+        #[cfg(feature = "md5_hash")]
+        5 => Some(HashFunction::MD5),
+        _ => None,
+    }
+}
+
+/// Returns the libSBF-cpp hash family of the hash function, if supported by the library
+pub fn hash_family(hash_function: HashFunction) -> Option<u32> {
+    match hash_function {
+        #[cfg(feature = "sha1_hash")]
+        HashFunction::SHA1 => Some(1),
+        #[cfg(feature = "md4_hash")]
+        HashFunction::MD4 => Some(4),
+        #[cfg(feature = "md5_hash")]
+        HashFunction::MD5 => Some(5),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+/// Reads `hash_number` salts from a libSBF-cpp salt file
+pub fn read_salts(mut reader: impl Read, hash_number: usize) -> io::Result<Salts> {
+    (0..hash_number)
+        .map(|_| {
+            let mut salt = vec![0; MAX_INPUT_SIZE];
+            reader.read_exact(&mut salt)?;
+            Ok(salt)
+        })
+        .collect::<io::Result<_>>()
+        .map(Salts::Stored)
+}
+
+/// Writes the salts as a libSBF-cpp salt file
+///
+/// Salts that are not [`MAX_INPUT_SIZE`] bytes long cannot be read by libSBF-cpp and are
+/// reported as an [`io::ErrorKind::InvalidData`] error wrapping [`Error::InvalidParameters`].
+pub fn write_salts(salts: &Salts, mut writer: impl Write) -> io::Result<()> {
+    for salt in salts.iter() {
+        if salt.len() != MAX_INPUT_SIZE {
+            return Err(invalid(Error::InvalidParameters));
+        }
+        writer.write_all(&salt)?;
+    }
+    writer.flush()
+}
+
+impl<U> SBF<U, HashFunction>
+where
+    U: Area,
+{
+    /// Constructor of a filter with the parameters and derivation of libSBF-cpp
+    ///
+    /// - `bit_mapping`: The filter has `2^bit_mapping` cells, at most `2^32`,
+    /// - `hash_function`: Hash function, see [`hash_function`] to convert a hash family,
+    /// - `salts`: Salts, usually loaded with [`read_salts`],
+    /// - `area_number`: Number of areas, including the empty area `0`.
+    pub fn new_libsbf(
+        bit_mapping: u32,
+        hash_function: HashFunction,
+        salts: Salts,
+        area_number: usize,
+    ) -> Result<Self, Error> {
+        if bit_mapping == 0 || bit_mapping > MAX_BIT_MAPPING {
+            return Err(Error::InvalidParameters);
+        }
+        let cells = 1usize
+            .checked_shl(bit_mapping)
+            .ok_or(Error::InvalidParameters)?;
+        if salts.is_empty() {
+            return Err(Error::ZeroHashes);
+        }
+        if area_number < 2 {
+            return Err(Error::NotEnoughAreas);
+        }
+        if area_number > Self::max_area_number() {
+            return Err(Error::TooManyAreas);
+        }
+
+        Ok(Self::from_parts(
+            cells,
+            salts,
+            hash_function,
+            area_number,
+            cfg!(feature = "metrics"),
+        )
+        .with_index_mode(IndexMode::LibSbf)?
+        .with_input_mode(InputMode::LibSbf))
+    }
+
+    /// Writes the cells in the libSBF-cpp text format, one `index;value` line for each cell
+    pub fn write_libsbf_cells(&self, mut writer: impl Write, mode: CellsMode) -> io::Result<()> {
+        for (index, cell) in self.filter.iter().enumerate() {
+            if mode == CellsMode::NonEmpty && cell.is_zero() {
+                continue;
+            }
+            let cell = cell.to_u64().ok_or_else(|| invalid(Error::CorruptData))?;
+            writeln!(writer, "{};{}", index, cell)?;
+        }
+        writer.flush()
+    }
+
+    /// Reads the cells from the libSBF-cpp text format, replacing the content of the filter
+    ///
+    /// Both `;` and `,` are accepted as separators, the cells that are not listed are empty.
+    /// Malformed lines and cells outside of the filter or of the areas are reported as an
+    /// [`io::ErrorKind::InvalidData`] error wrapping [`Error::CorruptData`],
+    /// [`Error::IndexOutOfBounds`] or [`Error::AreaOutOfRange`], in which case the filter is not
+    /// modified.
+    ///
+    /// The metrics only track the occupied cells, as the members are unknown.
+    pub fn read_libsbf_cells(&mut self, reader: impl Read) -> io::Result<()> {
+        let mut filter = Cells::new(self.filter.len());
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (index, value) = line
+                .split_once([';', ','])
+                .ok_or_else(|| invalid(Error::CorruptData))?;
+            let index: usize = index
+                .trim()
+                .parse()
+                .map_err(|_| invalid(Error::CorruptData))?;
+            let value: usize = value
+                .trim()
+                .parse()
+                .map_err(|_| invalid(Error::CorruptData))?;
+            let cell = filter
+                .get_mut(index)
+                .ok_or_else(|| invalid(Error::IndexOutOfBounds))?;
+            *cell = U::from_usize(value)
+                .filter(|_| value < self.area_number())
+                .ok_or_else(|| invalid(Error::AreaOutOfRange))?;
+        }
+        self.filter = filter;
+
+        #[cfg(feature = "metrics")]
+        if self.metrics_enabled {
+            let mut metrics = Metrics::new(self.filter.len(), self.salts.len(), self.area_number());
+            metrics.count_area_cells(&self.filter);
+            metrics.block_cells = self.metrics.block_cells;
+            self.metrics = metrics;
+        }
+
+        Ok(())
+    }
+}
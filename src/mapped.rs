@@ -0,0 +1,134 @@
+//! Memory-mapped SBF data structure
+//!
+//! [`MappedSBF`] memory-maps a filter written with [`SBF::write_to`] and reads its cells
+//! directly from the mapped file, so opening a filter does not load it in memory and processes
+//! mapping the same file share the page cache.
+//! The header of the file is padded to a multiple of the cache line size, so the blocks of
+//! [`IndexMode::Blocked`](crate::IndexMode::Blocked) stay aligned to the cache lines.
+//! Requires the `mmap` feature.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use byteorder::{ByteOrder, LittleEndian};
+use memmap2::Mmap;
+
+use crate::{
+    binary::Header,
+    cells::Cells,
+    data_structure::{lowest_cell, SBF},
+    error::Error,
+    types::{Area, HashFunction},
+};
+
+/// Read-only Spatial Bloom Filter backed by a memory-mapped file
+///
+/// The file must not be modified while it is mapped.
+#[derive(Debug)]
+pub struct MappedSBF<U>
+where
+    U: Area,
+{
+    /// Parameters and salts of the filter, its cells are in the mapped file
+    sbf: SBF<U, HashFunction>,
+    /// Mapped file
+    map: Mmap,
+    /// Position of the first cell in the file
+    offset: usize,
+    /// Number of bytes of each cell
+    width: usize,
+    /// Number of cells
+    cells: usize,
+}
+
+impl<U> MappedSBF<U>
+where
+    U: Area,
+{
+    /// Memory-maps the filter stored in the file
+    ///
+    /// Only the parameters and the salts are read, the checksum of the file is not checked, see
+    /// [`verify`](MappedSBF::verify).
+    /// Errors are reported as by [`SBF::read_from`].
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the filter is read-only, the documentation requires the file not to be modified
+        // while it is mapped
+        let map = unsafe { Mmap::map(&file)? };
+        Self::from_mmap(map)
+    }
+
+    /// Uses the memory-mapped filter, see [`open`](MappedSBF::open)
+    pub fn from_mmap(map: Mmap) -> io::Result<Self> {
+        let mut reader: &[u8] = &map;
+        let header = Header::read(&mut reader)?;
+        let offset = map.len() - reader.len();
+        let (width, cells) = (header.width, header.cells);
+
+        // The cells must be followed by the checksum
+        let cells_end = cells
+            .checked_mul(width)
+            .and_then(|len| len.checked_add(offset))
+            .and_then(|end| end.checked_add(4));
+        if cells_end != Some(map.len()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                Error::CorruptData,
+            ));
+        }
+
+        Ok(MappedSBF {
+            sbf: header.into_sbf(Cells::default(), false)?,
+            map,
+            offset,
+            width,
+            cells,
+        })
+    }
+
+    /// Checks the checksum of the whole file
+    ///
+    /// Reads all the cells, so it can take a while on very large filters.
+    pub fn verify(&self) -> Result<(), Error> {
+        let checksum_offset = self.map.len().saturating_sub(4);
+        let (data, checksum) = self.map.split_at(checksum_offset);
+        if checksum.len() == 4 && crc32fast::hash(data) == LittleEndian::read_u32(checksum) {
+            Ok(())
+        } else {
+            Err(Error::CorruptData)
+        }
+    }
+
+    /// Returns the number of areas, including the empty area `0`
+    pub fn area_number(&self) -> usize {
+        self.sbf.area_number()
+    }
+
+    /// Returns the number of cells
+    pub fn cells(&self) -> usize {
+        self.cells
+    }
+
+    /// Returns the content of a cell
+    pub fn get_cell(&self, index: usize) -> Result<U, Error> {
+        let start = index
+            .checked_mul(self.width)
+            .and_then(|start| start.checked_add(self.offset))
+            .filter(|_| index < self.cells)
+            .ok_or(Error::IndexOutOfBounds)?;
+        let bytes = self
+            .map
+            .get(start..start.saturating_add(self.width))
+            .ok_or(Error::IndexOutOfBounds)?;
+        U::from_u64(LittleEndian::read_uint(bytes, self.width)).ok_or(Error::TooManyAreas)
+    }
+
+    /// Check an input for presence in the filter, see [`SBF::check`].
+    ///
+    /// Returns [`Error::TooManyAreas`] if a cell does not fit in the area type `U`.
+    pub fn check(&self, content: impl AsRef<[u8]>) -> Result<U, Error> {
+        let indexes = self.sbf.calc_indexes_within(content.as_ref(), self.cells)?;
+        lowest_cell(&indexes, |i| self.get_cell(i))
+    }
+}
@@ -5,10 +5,22 @@ use rayon::prelude::*;
 #[cfg(feature = "serde_support")]
 use serde::{Deserialize, Serialize};
 
+use crate::error::Error;
+#[cfg(feature = "metrics")]
+use crate::types::Area;
+
+/// Default upper bound to the number of hash functions considered by the [`Planner`]
+const DEFAULT_MAX_HASH_NUMBER: usize = 32;
+
+/// Poisson probabilities below this fraction of the largest one are ignored
+const POISSON_EPSILON: f64 = 1e-15;
+
 /// The data structure that contains the metrics about the current `SBF` structure.
 ///
 /// This data structure is automatically added to each `SBF` if the feature `metrics` is enabled.
 /// It's not necessary and is disabled by default.
+/// The type itself is always available, to compute the prior probabilities of a filter and to
+/// plan its parameters with the [`Planner`].
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
 pub struct Metrics {
@@ -42,9 +54,160 @@ pub struct Metrics {
     pub area_isep: Vec<f64>,
     /// Prior area-specific safeness probability
     pub area_prior_safep: Vec<f64>,
+    /// Number of cells of each block of a blocked filter, see
+    /// [`IndexMode::Blocked`](crate::IndexMode::Blocked)
+    ///
+    /// When set the prior probabilities account for the uneven load of the blocks, while the
+    /// posterior ones, based on the occupied cells, are unchanged.
+    #[cfg_attr(feature = "serde_support", serde(default))]
+    pub block_cells: Option<usize>,
 }
 
 impl Metrics {
+    /// Creates the metrics of an empty filter
+    pub fn new(cells: usize, hash_number: usize, area_number: usize) -> Self {
+        Metrics {
+            cells,
+            hash_number,
+            members: 0,
+            collisions: 0,
+            safeness: 0.0,
+            area_number,
+            area_members: vec![0; area_number],
+            area_cells: vec![0; area_number],
+            area_expected_cells: vec![-1; area_number],
+            area_self_collisions: vec![0; area_number],
+            area_fpp: vec![-1.0; area_number],
+            area_isep: vec![-1.0; area_number],
+            area_prior_fpp: vec![-1.0; area_number],
+            area_prior_isep: vec![-1.0; area_number],
+            area_prior_safep: vec![-1.0; area_number],
+            block_cells: None,
+        }
+    }
+
+    /// Returns the prior probability that all the cells of an input are marked after the
+    /// insertion of the given number of members
+    ///
+    /// For a classic filter with `m` cells and `k` hash functions this is
+    /// `(1 - (1 - 1/m)^(k n))^k`.
+    /// In a blocked filter with blocks of `b` cells each block receives a Poisson distributed
+    /// number of members with mean `λ = n b / m`, so the probability is averaged over the members
+    /// of the block of the input: `Σ_i P(i; λ) (1 - (1 - 1/b)^(k i))^k`.
+    fn prior_marked(&self, members: usize) -> f64 {
+        let hash_number = self.hash_number as f64;
+        let marked = |cells: f64, members: f64| {
+            let p = 1.0 - 1.0 / cells;
+            let p = 1.0 - p.powf(hash_number * members);
+            p.powf(hash_number)
+        };
+        // A single block receives all the members
+        let (block_cells, blocks) = self.layout();
+        if blocks == 1 {
+            return marked(block_cells as f64, members as f64);
+        }
+        let lambda = members as f64 / blocks as f64;
+        let mode = lambda.floor();
+
+        // Sum the Poisson terms relative to the mode, walking away from it in both directions
+        let (mut total, mut weighted) = (1.0, marked(block_cells as f64, mode));
+        let (mut i, mut weight) = (mode, 1.0);
+        while weight > POISSON_EPSILON {
+            weight *= lambda / (i + 1.0);
+            i += 1.0;
+            total += weight;
+            weighted += weight * marked(block_cells as f64, i);
+        }
+        let (mut i, mut weight) = (mode, 1.0);
+        while weight > POISSON_EPSILON && i > 0.0 {
+            weight *= i / lambda;
+            i -= 1.0;
+            total += weight;
+            weighted += weight * marked(block_cells as f64, i);
+        }
+        weighted / total
+    }
+
+    /// Returns the prior probability that a cell is not marked after the insertion of the given
+    /// number of members
+    ///
+    /// For a classic filter with `m` cells and `k` hash functions this is `(1 - 1/m)^(k n)`.
+    /// In a blocked filter with `B` blocks of `b` cells each member falls in the block of the cell
+    /// with probability `1/B`, so the probability is `(1 - (1 - (1 - 1/b)^k) / B)^n`.
+    fn prior_unmarked(&self, members: usize) -> f64 {
+        let (block_cells, blocks) = self.layout();
+        let p = 1.0 - 1.0 / block_cells as f64;
+        if blocks == 1 {
+            return p.powf(self.hash_number as f64 * members as f64);
+        }
+        let p = 1.0 - (1.0 - p.powf(self.hash_number as f64)) / blocks as f64;
+        p.powf(members as f64)
+    }
+
+    /// Returns the number of cells of each block and the number of blocks used by the members
+    ///
+    /// A classic filter is a single block as large as the filter, a blocked filter only uses its
+    /// whole blocks.
+    fn layout(&self) -> (usize, usize) {
+        match self.block_cells {
+            Some(block_cells) if block_cells > 0 && self.cells > 0 => {
+                let block_cells = block_cells.min(self.cells);
+                (block_cells, self.cells / block_cells)
+            }
+            _ => (self.cells, 1),
+        }
+    }
+
+    /// Records the marking of a cell holding the `previous` area with the given area
+    #[cfg(feature = "metrics")]
+    pub(crate) fn record_cell(&mut self, previous: usize, area: usize) {
+        if previous == 0 {
+            // Cell is not marked
+            if let Some(cells) = self.area_cells.get_mut(area) {
+                *cells += 1;
+            }
+        } else if previous < area {
+            // Cell hash lower value than the input area
+            if let Some(cells) = self.area_cells.get_mut(previous) {
+                *cells = cells.saturating_sub(1);
+            }
+            if let Some(cells) = self.area_cells.get_mut(area) {
+                *cells += 1;
+            }
+            self.collisions += 1;
+        } else if previous == area {
+            // Cell hash same value than input area
+            self.collisions += 1;
+            if let Some(collisions) = self.area_self_collisions.get_mut(area) {
+                *collisions += 1;
+            }
+        } else {
+            // Cell hash higher value than input area
+            self.collisions += 1;
+        }
+    }
+
+    /// Records the insertion of a member of the given area
+    #[cfg(feature = "metrics")]
+    #[allow(clippy::arithmetic_side_effects)]
+    pub(crate) fn record_member(&mut self, area: usize) {
+        self.members += 1;
+        if let Some(members) = self.area_members.get_mut(area) {
+            *members += 1;
+        }
+    }
+
+    /// Recounts the cells occupied by each area
+    #[cfg(feature = "metrics")]
+    pub(crate) fn count_area_cells<U: Area>(&mut self, cells: &[U]) {
+        self.area_cells.iter_mut().for_each(|c| *c = 0);
+        for cell in cells.iter().filter(|c| !c.is_zero()) {
+            if let Some(c) = self.area_cells.get_mut(cell.to_usize().unwrap_or_default()) {
+                *c += 1;
+            }
+        }
+    }
+
     /// Returns the number of inserted elements for the input area
     pub fn get_area_members(&self, index: usize) -> Option<usize> {
         self.area_members.get(index).cloned()
@@ -70,16 +233,18 @@ impl Metrics {
     }
 
     /// Returns the expected emersion value for the input area
+    ///
+    /// The probability that a cell is not overwritten by the members of the higher areas, which
+    /// accounts for the blocks of a blocked filter.
     pub fn get_expected_area_emersion(&self, area: usize) -> f64 {
         let cells_with_greater_area_index: usize =
             self.area_members.par_iter().skip(area).skip(1).sum();
-        let p = 1.0f64 - 1.0f64 / self.cells as f64;
-        p.pow(self.hash_number as f64 * cells_with_greater_area_index as f64)
+        self.prior_unmarked(cells_with_greater_area_index)
     }
 
     /// Returns the emersion value for the input area
     pub fn get_area_emersion(&self, area: usize) -> Option<f64> {
-        if self.area_cells[area] == 0 || self.hash_number == 0 {
+        if matches!(self.area_cells.get(area), None | Some(0)) || self.hash_number == 0 {
             None
         } else {
             match (
@@ -100,9 +265,7 @@ impl Metrics {
 
     /// Returns the prior false positive probability over the entire filter
     pub fn get_filter_prior_fpp(&self) -> f64 {
-        let p = 1.0 - 1.0 / self.cells as f64;
-        let p = 1.0 - p.powf(self.hash_number as f64 * self.members as f64);
-        p.powf(self.hash_number as f64)
+        self.prior_marked(self.members)
     }
 
     /// Computes posterior area-specific false positives probability (fpp)
@@ -124,15 +287,14 @@ impl Metrics {
     }
 
     /// Computes prior area-specific false positives probability (prior_fpp)
+    ///
+    /// Only `area_prior_fpp` is written, the posterior `area_fpp` is left untouched.
+    /// The exponents are computed in floating point, so they do not overflow for large filters.
     pub fn set_prior_area_fpp(&mut self) {
         (1..self.area_number).rev().for_each(|i| {
             let c: usize = (i..self.area_number).map(|j| self.area_members[j]).sum();
 
-            let p = 1.0 - 1.0 / self.cells as f64;
-            let p = 1.0 - p.powi((self.hash_number * c) as i32);
-            let p = p.powi(self.hash_number as i32);
-
-            self.area_fpp[i] = p;
+            self.area_prior_fpp[i] = self.prior_marked(c);
 
             (i..self.area_number - 1).for_each(|j| {
                 self.area_prior_fpp[i] -= self.area_prior_fpp[j + 1];
@@ -154,6 +316,10 @@ impl Metrics {
     /// Computes prior area-specific inter-set error probability (prior_isep),
     /// computes prior area-specific safeness probability (prior_safep) and
     /// the overall safeness probability for the entire filter (safeness)
+    ///
+    /// The inter-set error probability of area `i` is the probability that all the `k` cells of
+    /// one of its members are overwritten by the `n` members of the higher areas,
+    /// `(1 - (1 - 1/m)^(k n))^k`, and its safeness is the probability that none of its members is.
     pub fn set_prior_area_isep(&mut self) {
         let mut p3 = 1.0;
         (1..self.area_number).rev().for_each(|i| {
@@ -162,11 +328,9 @@ impl Metrics {
                 .map(|j| self.area_members[j])
                 .sum();
 
-            let p1 = 1.0 - 1.0 / self.cells as f64;
-            let p1 = 1.0 - p1.powi((self.hash_number * n_fill) as i32);
-            let p1 = p1.powi(self.area_members[i] as i32);
+            let p1 = self.prior_marked(n_fill);
 
-            let p2 = (1.0 - p1).powi(self.area_members[i] as i32);
+            let p2 = (1.0 - p1).powf(self.area_members[i] as f64);
 
             p3 *= p2;
 
@@ -178,13 +342,192 @@ impl Metrics {
     }
 
     /// Computes the expected number of cells for each area (expected_cells)
+    ///
+    /// In a blocked filter only the cells of the whole blocks are counted.
     pub fn set_expected_area_cells(&mut self) {
+        let (block_cells, blocks) = self.layout();
         (1..self.area_number).rev().for_each(|i| {
             let n_fill: usize = (i..self.area_number).map(|j| self.area_members[j]).sum();
 
-            let p1 = 1.0 - 1.0 / self.cells as f64;
-            let p2 = p1.pow((self.hash_number * n_fill) as f64);
-            self.area_expected_cells[i] = (self.cells as f64 * p1 * p2) as i64;
+            let p1 = 1.0 - 1.0 / block_cells as f64;
+            let p2 = self.prior_unmarked(n_fill);
+            self.area_expected_cells[i] = ((block_cells * blocks) as f64 * p1 * p2) as i64;
+        })
+    }
+}
+
+/// Parameters planner based on the prior area-specific probabilities
+///
+/// Differently from [`SBF::new_optimal`](crate::SBF::new_optimal), which only uses the classic
+/// Bloom filter formula, the planner takes into account the expected members of each area and
+/// bounds both the prior area-specific false positive probability and the prior area-specific
+/// inter-set error probability, as computed by [`Metrics::set_prior_area_fpp`] and
+/// [`Metrics::set_prior_area_isep`].
+///
+/// The expected members are indexed by area, the index `0` is the empty area and is ignored.
+#[derive(Clone, Debug)]
+pub struct Planner {
+    area_members: Vec<usize>,
+    max_area_fpp: Vec<Option<f64>>,
+    max_area_isep: Vec<Option<f64>>,
+    max_hash_number: usize,
+    block_cells: Option<usize>,
+}
+
+/// Parameters chosen by the [`Planner`] and the predicted probabilities for each area
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct Plan {
+    /// Number of cells in the filter
+    pub cells: usize,
+    /// Number of hash functions
+    pub hash_number: usize,
+    /// Number of areas, including the empty area `0`
+    pub area_number: usize,
+    /// Predicted prior false positive probability for each area
+    pub area_prior_fpp: Vec<f64>,
+    /// Predicted prior inter-set error probability for each area
+    pub area_prior_isep: Vec<f64>,
+    /// Predicted prior area-specific safeness probability
+    pub area_prior_safep: Vec<f64>,
+    /// Predicted safeness probability over the entire filter
+    pub safeness: f64,
+}
+
+impl Planner {
+    /// Creates a planner for the expected members of each area
+    pub fn new(area_members: Vec<usize>) -> Self {
+        let area_number = area_members.len();
+        Planner {
+            area_members,
+            max_area_fpp: vec![None; area_number],
+            max_area_isep: vec![None; area_number],
+            max_hash_number: DEFAULT_MAX_HASH_NUMBER,
+            block_cells: None,
+        }
+    }
+
+    /// Bounds the prior false positive probability of every area
+    pub fn max_fpp(mut self, max_fpp: f64) -> Self {
+        self.max_area_fpp
+            .iter_mut()
+            .for_each(|v| *v = Some(max_fpp));
+        self
+    }
+
+    /// Bounds the prior false positive probability of a single area
+    pub fn max_area_fpp(mut self, area: usize, max_fpp: f64) -> Self {
+        if let Some(v) = self.max_area_fpp.get_mut(area) {
+            *v = Some(max_fpp);
+        }
+        self
+    }
+
+    /// Bounds the prior inter-set error probability of every area
+    pub fn max_isep(mut self, max_isep: f64) -> Self {
+        self.max_area_isep
+            .iter_mut()
+            .for_each(|v| *v = Some(max_isep));
+        self
+    }
+
+    /// Bounds the prior inter-set error probability of a single area
+    pub fn max_area_isep(mut self, area: usize, max_isep: f64) -> Self {
+        if let Some(v) = self.max_area_isep.get_mut(area) {
+            *v = Some(max_isep);
+        }
+        self
+    }
+
+    /// Sets the largest number of hash functions to consider
+    pub fn max_hash_number(mut self, max_hash_number: usize) -> Self {
+        self.max_hash_number = max_hash_number;
+        self
+    }
+
+    /// Plans a blocked filter with blocks of the given number of cells, see
+    /// [`IndexMode::Blocked`](crate::IndexMode::Blocked) and
+    /// [`SBF::block_cells`](crate::SBF::block_cells)
+    pub fn blocked(mut self, block_cells: usize) -> Self {
+        self.block_cells = Some(block_cells);
+        self
+    }
+
+    /// Computes the prior metrics of a filter with the given parameters
+    pub(crate) fn prior_metrics(&self, cells: usize, hash_number: usize) -> Metrics {
+        let mut metrics = Metrics::new(cells, hash_number, self.area_members.len());
+        metrics.block_cells = self.block_cells;
+        metrics.area_members = self.area_members.clone();
+        metrics.set_prior_area_fpp();
+        metrics.set_prior_area_isep();
+        metrics
+    }
+
+    /// Returns `true` if the metrics satisfy all the bounds
+    pub(crate) fn is_satisfied(&self, metrics: &Metrics) -> bool {
+        let below = |bounds: &[Option<f64>], values: &[f64]| {
+            bounds
+                .iter()
+                .zip(values)
+                .skip(1)
+                .all(|(bound, value)| bound.is_none_or(|bound| *value <= bound))
+        };
+        below(&self.max_area_fpp, &metrics.area_prior_fpp)
+            && below(&self.max_area_isep, &metrics.area_prior_isep)
+    }
+
+    /// Finds the smallest number of cells that satisfies the bounds with the given hash number
+    fn min_cells(&self, hash_number: usize) -> Option<usize> {
+        // Exponential search of a satisfying upper bound
+        let mut high = 1usize;
+        while !self.is_satisfied(&self.prior_metrics(high, hash_number)) {
+            high = high.checked_mul(2).filter(|high| *high <= 1 << 48)?;
+        }
+
+        // Binary search of the smallest satisfying number of cells
+        let mut low = high / 2;
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            if self.is_satisfied(&self.prior_metrics(mid, hash_number)) {
+                high = mid;
+            } else {
+                low = mid;
+            }
+        }
+        Some(high)
+    }
+
+    /// Chooses the smallest filter that satisfies the bounds
+    ///
+    /// Among the filters with the same number of cells the one with less hash functions is
+    /// chosen.
+    pub fn plan(&self) -> Result<Plan, Error> {
+        let valid_bound = |bound: &Option<f64>| bound.is_none_or(|b| b > 0.0 && b < 1.0);
+        if self.area_members.len() < 2 {
+            return Err(Error::NotEnoughAreas);
+        }
+        if self.max_hash_number == 0 {
+            return Err(Error::ZeroHashes);
+        }
+        if !self.max_area_fpp.iter().all(valid_bound) || !self.max_area_isep.iter().all(valid_bound)
+        {
+            return Err(Error::InvalidParameters);
+        }
+
+        let (cells, hash_number) = (1..=self.max_hash_number)
+            .filter_map(|hash_number| Some((self.min_cells(hash_number)?, hash_number)))
+            .min()
+            .ok_or(Error::InvalidParameters)?;
+
+        let metrics = self.prior_metrics(cells, hash_number);
+        Ok(Plan {
+            cells,
+            hash_number,
+            area_number: metrics.area_number,
+            area_prior_fpp: metrics.area_prior_fpp,
+            area_prior_isep: metrics.area_prior_isep,
+            area_prior_safep: metrics.area_prior_safep,
+            safeness: metrics.safeness,
         })
     }
 }
@@ -0,0 +1,245 @@
+//! Set operations between SBF data structures
+//!
+//! Two filters are compatible when they share the same salts, hash function, number of cells,
+//! number of areas and index and input modes, so the same input is mapped to the same cells in
+//! both of them.
+//! Since the insertion keeps the highest area on each cell, merging compatible filters cell by
+//! cell with `max` gives the same filter obtained inserting all the inputs in a single one.
+//!
+//! The intersection and the difference of two filters, and the report of the cells that differ,
+//! can be used to audit how the content of a filter changed between two builds.
+
+use std::ops::BitOr;
+
+use rayon::prelude::*;
+
+use crate::{
+    cells::Cells,
+    data_structure::SBF,
+    error::Error,
+    hasher::SbfHasher,
+    types::{Area, CellDiff},
+};
+
+impl<U, H> SBF<U, H>
+where
+    H: SbfHasher + PartialEq,
+    U: Area,
+{
+    /// Checks if the two filters map the inputs to the same cells
+    pub fn is_compatible(&self, other: &SBF<U, H>) -> bool {
+        self.filter.len() == other.filter.len()
+            && self.area_number() == other.area_number()
+            && self.index_mode == other.index_mode
+            && self.input_mode == other.input_mode
+            && self.hash_function == other.hash_function
+            && self.salts == other.salts
+    }
+
+    /// Merges the content of another compatible filter into this one.
+    ///
+    /// Each cell keeps the highest area of the two filters, so the result is the same filter
+    /// obtained inserting the inputs of both filters in a single one.
+    /// Returns [`Error::IncompatibleFilters`] if the filters do not share the same parameters and
+    /// salts.
+    ///
+    /// The occupied cells of the metrics are recomputed from the merged cells, while the members
+    /// and the collisions are combined from the metrics of the two filters.
+    pub fn merge(&mut self, other: &SBF<U, H>) -> Result<(), Error> {
+        if !self.is_compatible(other) {
+            return Err(Error::IncompatibleFilters);
+        }
+
+        #[cfg(feature = "metrics")]
+        if self.metrics_enabled {
+            self.merge_metrics(other);
+        }
+
+        self.filter
+            .par_iter_mut()
+            .zip(other.filter.par_iter())
+            .for_each(|(cell, other)| {
+                if *cell < *other {
+                    *cell = *other;
+                }
+            });
+
+        Ok(())
+    }
+
+    /// Combines the metrics of another compatible filter, before the cells are merged
+    #[cfg(feature = "metrics")]
+    #[allow(clippy::arithmetic_side_effects)]
+    fn merge_metrics(&mut self, other: &SBF<U, H>) {
+        let metrics = &mut self.metrics;
+        metrics.members += other.metrics.members;
+        metrics.collisions += other.metrics.collisions;
+        metrics
+            .area_members
+            .iter_mut()
+            .zip(&other.metrics.area_members)
+            .for_each(|(members, other)| *members += other);
+        metrics
+            .area_self_collisions
+            .iter_mut()
+            .zip(&other.metrics.area_self_collisions)
+            .for_each(|(collisions, other)| *collisions += other);
+
+        metrics.area_cells.iter_mut().for_each(|cells| *cells = 0);
+        for (cell, other) in self.filter.iter().zip(&other.filter) {
+            let (cell, other) = (
+                cell.to_usize().unwrap_or_default(),
+                other.to_usize().unwrap_or_default(),
+            );
+            if cell != 0 && other != 0 {
+                // The cell is marked in both filters, the second marking is a collision
+                metrics.collisions += 1;
+                if cell == other {
+                    if let Some(collisions) = metrics.area_self_collisions.get_mut(cell) {
+                        *collisions += 1;
+                    }
+                }
+            }
+            match metrics.area_cells.get_mut(cell.max(other)) {
+                Some(cells) if cell != 0 || other != 0 => *cells += 1,
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns a filter holding only the cells on which the two compatible filters agree.
+    ///
+    /// The other cells are empty.
+    /// Returns [`Error::IncompatibleFilters`] if the filters do not share the same parameters and
+    /// salts.
+    pub fn intersection(&self, other: &SBF<U, H>) -> Result<SBF<U, H>, Error>
+    where
+        H: Clone,
+    {
+        self.combine(
+            other,
+            |cell, other| {
+                if cell == other {
+                    cell
+                } else {
+                    U::zero()
+                }
+            },
+        )
+    }
+
+    /// Returns a filter holding only the cells of this filter with an area higher than the one
+    /// of the other compatible filter.
+    ///
+    /// The other cells are empty.
+    /// Returns [`Error::IncompatibleFilters`] if the filters do not share the same parameters and
+    /// salts.
+    pub fn difference(&self, other: &SBF<U, H>) -> Result<SBF<U, H>, Error>
+    where
+        H: Clone,
+    {
+        self.combine(
+            other,
+            |cell, other| {
+                if cell > other {
+                    cell
+                } else {
+                    U::zero()
+                }
+            },
+        )
+    }
+
+    /// Returns the cells holding a different value in the two compatible filters, in ascending
+    /// order of index.
+    ///
+    /// Returns [`Error::IncompatibleFilters`] if the filters do not share the same parameters and
+    /// salts.
+    pub fn diff(&self, other: &SBF<U, H>) -> Result<Vec<CellDiff<U>>, Error> {
+        if !self.is_compatible(other) {
+            return Err(Error::IncompatibleFilters);
+        }
+
+        Ok(self
+            .filter
+            .par_iter()
+            .zip(other.filter.par_iter())
+            .enumerate()
+            .filter(|(_, (left, right))| left != right)
+            .map(|(cell_index, (left, right))| CellDiff {
+                cell_index,
+                left: *left,
+                right: *right,
+            })
+            .collect())
+    }
+
+    /// Builds a new filter combining the cells of two compatible filters.
+    ///
+    /// The members of the new filter are unknown, so its metrics only track the occupied cells.
+    fn combine<F>(&self, other: &SBF<U, H>, f: F) -> Result<SBF<U, H>, Error>
+    where
+        F: Fn(U, U) -> U + Sync,
+        H: Clone,
+    {
+        if !self.is_compatible(other) {
+            return Err(Error::IncompatibleFilters);
+        }
+
+        let mut filter = Cells::new(self.filter.len());
+        filter
+            .par_iter_mut()
+            .zip(self.filter.par_iter().zip(other.filter.par_iter()))
+            .for_each(|(combined, (cell, other))| *combined = f(*cell, *other));
+        #[cfg(feature = "metrics")]
+        let metrics_enabled = self.metrics_enabled;
+        #[cfg(not(feature = "metrics"))]
+        let metrics_enabled = false;
+        #[cfg_attr(not(feature = "metrics"), allow(unused_mut))]
+        let mut combined = SBF::from_cells(
+            filter,
+            self.salts.clone(),
+            self.hash_function.clone(),
+            self.area_number(),
+            metrics_enabled,
+        )
+        .with_index_mode(self.index_mode)?
+        .with_input_mode(self.input_mode);
+
+        #[cfg(feature = "metrics")]
+        if combined.metrics_enabled {
+            combined.metrics.count_area_cells(&combined.filter);
+        }
+
+        Ok(combined)
+    }
+}
+
+/// Union of two compatible filters, see [`SBF::merge`]
+impl<U, H> BitOr<&SBF<U, H>> for &SBF<U, H>
+where
+    H: SbfHasher + PartialEq + Clone,
+    U: Area,
+{
+    type Output = Result<SBF<U, H>, Error>;
+
+    fn bitor(self, rhs: &SBF<U, H>) -> Self::Output {
+        let mut merged = self.clone();
+        merged.merge(rhs)?;
+        Ok(merged)
+    }
+}
+
+/// Union of two compatible filters, reusing the left filter, see [`SBF::merge`]
+impl<U, H> BitOr<&SBF<U, H>> for SBF<U, H>
+where
+    H: SbfHasher + PartialEq,
+    U: Area,
+{
+    type Output = Result<SBF<U, H>, Error>;
+
+    fn bitor(mut self, rhs: &SBF<U, H>) -> Self::Output {
+        self.merge(rhs)?;
+        Ok(self)
+    }
+}
@@ -0,0 +1,231 @@
+//! Bit-packed SBF data structure
+//!
+//! [`PackedSBF`] stores each cell in the smallest number of bits that can hold all the areas of
+//! the filter, `ceil(log2(area_number))` bits where `area_number` includes the empty area `0`.
+//! For example a filter with 3 areas besides the empty one uses 2 bits per cell instead of the
+//! 8 bits of a `u8` cell.
+//! The number of areas is the one of the filter, or the one given to
+//! [`with_area_number`](PackedSBF::with_area_number) for filters built with [`SBF::new`], which
+//! accept every area of `U`.
+
+#[cfg(feature = "serde_support")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data_structure::{lowest_cell, SBF},
+    error::Error,
+    hasher::SbfHasher,
+    types::{Area, HashFunction},
+};
+
+/// Number of bits of each word of the packed cells
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// Spatial Bloom Filter storing its cells in the smallest number of bits
+///
+/// Built from an [`SBF`], whose content, parameters and salts it reuses, and converted back into
+/// one with [`into_sbf`](PackedSBF::into_sbf).
+/// Insertions and checks answer exactly as [`SBF::insert`] and [`SBF::check`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct PackedSBF<U, H = HashFunction>
+where
+    U: Area,
+{
+    /// Parameters, salts and metrics of the filter, its cells are moved in `words`
+    sbf: SBF<U, H>,
+    /// Packed cells, each cell may span two words
+    words: Vec<u64>,
+    /// Number of bits of each cell
+    bits: usize,
+    /// Number of areas that fit in a cell, including the empty area `0`
+    area_number: usize,
+    /// Number of cells
+    cells: usize,
+}
+
+impl<U, H> PackedSBF<U, H>
+where
+    H: SbfHasher,
+    U: Area,
+{
+    /// Builds a packed filter with the content, parameters and salts of the filter
+    ///
+    /// The number of bits of each cell is given by the number of areas of the filter, set with
+    /// [`SbfBuilder::area_number`](crate::builder::SbfBuilder::area_number).
+    /// Filters built with [`SBF::new`] accept every area of `U` and save no space, use
+    /// [`with_area_number`](PackedSBF::with_area_number) for them.
+    pub fn new(sbf: SBF<U, H>) -> Result<Self, Error> {
+        let area_number = sbf.area_number();
+        Self::with_area_number(sbf, area_number)
+    }
+
+    /// Builds a packed filter with the content, parameters and salts of the filter, whose cells
+    /// hold `area_number` areas, including the empty area `0`
+    ///
+    /// Returns [`Error::InvalidParameters`] if `area_number` is lower than 2 or higher than the
+    /// areas of the filter, and [`Error::AreaOutOfRange`] if a cell of the filter holds an area
+    /// that does not fit.
+    pub fn with_area_number(mut sbf: SBF<U, H>, area_number: usize) -> Result<Self, Error> {
+        if area_number < 2 || area_number > sbf.area_number() {
+            return Err(Error::InvalidParameters);
+        }
+        let max_area = (area_number - 1) as u64;
+        let bits = (u64::BITS - max_area.leading_zeros()) as usize;
+
+        let filter = std::mem::take(&mut sbf.filter);
+        let cells = filter.len();
+        let words = cells
+            .checked_mul(bits)
+            .map(|bits| bits.div_ceil(WORD_BITS))
+            .ok_or(Error::TooManyAreas)?;
+        let mut packed = PackedSBF {
+            sbf,
+            words: vec![0; words],
+            bits,
+            area_number,
+            cells,
+        };
+        for (index, cell) in filter.into_iter().enumerate() {
+            let value = cell.to_u64().ok_or(Error::AreaOutOfRange)?;
+            packed.write(index, value)?;
+        }
+        Ok(packed)
+    }
+
+    /// Converts the packed filter back into a filter
+    pub fn into_sbf(self) -> SBF<U, H> {
+        let filter = (0..self.cells)
+            .map(|index| self.get_cell(index).unwrap_or_else(|_| U::zero()))
+            .collect();
+        let mut sbf = self.sbf;
+        sbf.filter = filter;
+        sbf
+    }
+
+    /// Returns the number of bits of each cell
+    pub fn bits_per_cell(&self) -> usize {
+        self.bits
+    }
+
+    /// Returns the number of areas that fit in a cell, including the empty area `0`
+    pub fn area_number(&self) -> usize {
+        self.area_number
+    }
+
+    /// Returns the number of cells
+    pub fn cells(&self) -> usize {
+        self.cells
+    }
+
+    /// Returns the parameters, salts and metrics of the filter
+    ///
+    /// The cells of the returned filter are empty, use [`get_cell`](PackedSBF::get_cell) to read
+    /// them.
+    pub fn parameters(&self) -> &SBF<U, H> {
+        &self.sbf
+    }
+
+    /// Returns the number of bytes used by the packed cells
+    pub fn size_in_bytes(&self) -> usize {
+        self.words.len() * std::mem::size_of::<u64>()
+    }
+
+    /// Returns the content of a cell
+    pub fn get_cell(&self, index: usize) -> Result<U, Error> {
+        U::from_u64(self.read(index)?).ok_or(Error::TooManyAreas)
+    }
+
+    /// Reads the bits of a cell
+    fn read(&self, index: usize) -> Result<u64, Error> {
+        if index >= self.cells {
+            return Err(Error::IndexOutOfBounds);
+        }
+        let (word, shift) = self.position(index);
+        let mask = self.mask();
+        let low = self.words.get(word).ok_or(Error::IndexOutOfBounds)? >> shift;
+        let value = match (shift + self.bits).checked_sub(WORD_BITS) {
+            // The cell continues in the next word
+            Some(high_bits) if high_bits > 0 => {
+                let high = self.words.get(word + 1).ok_or(Error::IndexOutOfBounds)?;
+                low | (high << (self.bits - high_bits))
+            }
+            _ => low,
+        };
+        Ok(value & mask)
+    }
+
+    /// Writes the bits of a cell, the value must be one of the areas of the filter
+    fn write(&mut self, index: usize, value: u64) -> Result<(), Error> {
+        if index >= self.cells {
+            return Err(Error::IndexOutOfBounds);
+        }
+        if value >= self.area_number as u64 {
+            return Err(Error::AreaOutOfRange);
+        }
+        let (word, shift) = self.position(index);
+        let mask = self.mask();
+        let low = self.words.get_mut(word).ok_or(Error::IndexOutOfBounds)?;
+        *low = (*low & !(mask << shift)) | (value << shift);
+        if let Some(high_bits) = (shift + self.bits).checked_sub(WORD_BITS) {
+            if high_bits > 0 {
+                // The cell continues in the next word
+                let low_bits = self.bits - high_bits;
+                let high = self
+                    .words
+                    .get_mut(word + 1)
+                    .ok_or(Error::IndexOutOfBounds)?;
+                *high = (*high & !(mask >> low_bits)) | (value >> low_bits);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the word and the bit offset of the first bit of a cell
+    fn position(&self, index: usize) -> (usize, usize) {
+        let bit = index * self.bits;
+        (bit / WORD_BITS, bit % WORD_BITS)
+    }
+
+    /// Returns the mask of the bits of a cell
+    fn mask(&self) -> u64 {
+        u64::MAX >> (WORD_BITS - self.bits)
+    }
+
+    /// Check an input for presence in the filter, see [`SBF::check`].
+    pub fn check(&self, content: impl AsRef<[u8]>) -> Result<U, Error> {
+        let indexes = self.sbf.calc_indexes_within(content.as_ref(), self.cells)?;
+        lowest_cell(&indexes, |i| self.get_cell(i))
+    }
+
+    /// Insert the content in the filter associated to the given area, see [`SBF::insert`].
+    pub fn insert(&mut self, content: impl AsRef<[u8]>, area: U) -> Result<(), Error> {
+        #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+        let area_index = self.sbf.area_index(area)?;
+        if area_index >= self.area_number {
+            return Err(Error::AreaOutOfRange);
+        }
+        let value = area_index as u64;
+        let indexes = self.sbf.calc_indexes_within(content.as_ref(), self.cells)?;
+        if indexes.iter().any(|i| *i >= self.cells) {
+            return Err(Error::IndexOutOfBounds);
+        }
+
+        for index in indexes {
+            let previous = self.read(index)?;
+            #[cfg(feature = "metrics")]
+            if self.sbf.metrics_enabled {
+                self.sbf.metrics.record_cell(previous as usize, area_index);
+            }
+            if previous < value {
+                self.write(index, value)?;
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        if self.sbf.metrics_enabled {
+            self.sbf.metrics.record_member(area_index);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+//! Scalable SBF data structure
+//!
+//! [`ScalableSBF`] chains progressively larger filters, like scalable Bloom filters: when the
+//! fill ratio of the last filter reaches its threshold a new, larger filter is added and used for
+//! the following insertions.
+//! The false positive probability of a filter is about its fill ratio raised to the number of
+//! hashes, so the fill threshold also bounds the false positive probability of each filter.
+//! The threshold of each new filter is tightened, keeping the overall false positive probability
+//! bounded as the filter grows.
+
+use crate::{
+    builder::SbfBuilder,
+    data_structure::SBF,
+    error::Error,
+    hasher::SbfHasher,
+    types::{Area, HashFunction},
+};
+
+/// Default maximum fill ratio of the first layer
+const DEFAULT_MAX_FILL: f64 = 0.5;
+/// Default growth factor of the number of cells of each new layer
+const DEFAULT_GROWTH_FACTOR: usize = 2;
+/// Default tightening ratio of the maximum fill ratio of each new layer
+const DEFAULT_TIGHTENING_RATIO: f64 = 0.9;
+
+/// Spatial Bloom Filter that grows as elements are inserted
+///
+/// Each layer is built with the parameters of the given [`SbfBuilder`], except for the number of
+/// cells, which is multiplied by the growth factor at each new layer.
+/// An element is inserted in the last layer only, while checks look through all the layers and
+/// return the highest area found, the same way a single filter resolves collisions.
+#[derive(Clone, Debug)]
+pub struct ScalableSBF<U, H = HashFunction>
+where
+    U: Area,
+{
+    /// Builder of the layers
+    builder: SbfBuilder<U, H>,
+    /// Layers, from the oldest to the newest
+    layers: Vec<SBF<U, H>>,
+    /// Number of marked cells of each layer
+    occupied: Vec<usize>,
+    /// Maximum fill ratio of the first layer
+    max_fill: f64,
+    /// Growth factor of the number of cells of each new layer
+    growth_factor: usize,
+    /// Tightening ratio of the maximum fill ratio of each new layer
+    tightening_ratio: f64,
+}
+
+impl<U, H> ScalableSBF<U, H>
+where
+    H: SbfHasher + Clone,
+    U: Area,
+{
+    /// Builds a scalable filter whose first layer is built by the builder
+    ///
+    /// The first layer grows when half of its cells are marked, each new layer has twice the
+    /// cells of the previous one and a maximum fill ratio 0.9 times the previous one.
+    pub fn new(builder: SbfBuilder<U, H>) -> Result<Self, Error> {
+        Self::with_growth(
+            builder,
+            DEFAULT_MAX_FILL,
+            DEFAULT_GROWTH_FACTOR,
+            DEFAULT_TIGHTENING_RATIO,
+        )
+    }
+
+    /// Builds a scalable filter with the given growth parameters
+    ///
+    /// - `max_fill`: Fraction of marked cells of the first layer that triggers a new layer,
+    /// - `growth_factor`: Factor multiplying the number of cells of each new layer,
+    /// - `tightening_ratio`: Factor multiplying the maximum fill ratio of each new layer.
+    ///
+    /// Returns [`Error::InvalidParameters`] if the fill and tightening ratios are not in `(0, 1]`
+    /// or the growth factor is zero.
+    pub fn with_growth(
+        builder: SbfBuilder<U, H>,
+        max_fill: f64,
+        growth_factor: usize,
+        tightening_ratio: f64,
+    ) -> Result<Self, Error> {
+        let is_ratio = |ratio: f64| ratio > 0.0 && ratio <= 1.0;
+        if !is_ratio(max_fill) || !is_ratio(tightening_ratio) || growth_factor == 0 {
+            return Err(Error::InvalidParameters);
+        }
+
+        let first = builder.clone().build()?;
+        Ok(ScalableSBF {
+            builder,
+            layers: vec![first],
+            occupied: vec![0],
+            max_fill,
+            growth_factor,
+            tightening_ratio,
+        })
+    }
+
+    /// Returns the layers of the filter, from the oldest to the newest
+    pub fn layers(&self) -> &[SBF<U, H>] {
+        &self.layers
+    }
+
+    /// Check an input for presence in the filter.
+    ///
+    /// Returns `0` if the input is not been inserted or the highest area found in the layers, see
+    /// [`SBF::check`].
+    pub fn check(&self, content: impl AsRef<[u8]>) -> Result<U, Error> {
+        let content = content.as_ref();
+        self.layers.iter().try_fold(U::zero(), |max, layer| {
+            layer.check(content).map(|area| max.max(*area))
+        })
+    }
+
+    /// Insert the content in the newest layer associated to the given area, adding a new layer
+    /// first if the newest one is full.
+    pub fn insert(&mut self, content: impl AsRef<[u8]>, area: U) -> Result<(), Error> {
+        if self.is_full() {
+            self.grow()?;
+        }
+
+        let (layer, occupied) = match (self.layers.last_mut(), self.occupied.last_mut()) {
+            (Some(layer), Some(occupied)) => (layer, occupied),
+            _ => return Err(Error::CorruptData),
+        };
+        let area_index = layer.area_index(area)?;
+        let indexes = layer.calc_indexes(content.as_ref())?;
+
+        // Cells that are empty before the insertion will hold the area
+        let mut empty: Vec<usize> = indexes
+            .iter()
+            .copied()
+            .filter(|i| layer.filter.get(*i).is_some_and(|cell| cell.is_zero()))
+            .collect();
+        empty.sort_unstable();
+        empty.dedup();
+
+        layer.set_cells(&indexes, area, area_index)?;
+        *occupied = occupied.saturating_add(empty.len());
+        Ok(())
+    }
+
+    /// Checks if the newest layer reached its maximum fill ratio
+    fn is_full(&self) -> bool {
+        match (self.layers.last(), self.occupied.last()) {
+            (Some(layer), Some(occupied)) => {
+                let max_fill = self.max_fill
+                    * self
+                        .tightening_ratio
+                        .powi(self.layers.len().saturating_sub(1) as i32);
+                *occupied as f64 >= max_fill * layer.filter.len() as f64
+            }
+            _ => true,
+        }
+    }
+
+    /// Adds a new layer, larger than the newest one
+    fn grow(&mut self) -> Result<(), Error> {
+        let cells = self
+            .layers
+            .last()
+            .map_or(0, |layer| layer.filter.len())
+            .checked_mul(self.growth_factor)
+            .ok_or(Error::InvalidParameters)?;
+        self.layers.push(self.builder.clone().cells(cells).build()?);
+        self.occupied.push(0);
+        Ok(())
+    }
+}
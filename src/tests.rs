@@ -1,14 +1,49 @@
 use std::error::Error;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
 use rayon::prelude::*;
 
-use crate::data_structure::SBF;
-use crate::types::HashFunction;
+use crate::builder::SbfBuilder;
+use crate::collect::{SbfCollect, SbfParCollect};
+use crate::concurrent::ConcurrentSBF;
+use crate::counting::CountingSBF;
+use crate::data_structure::{BATCH_CHUNK, SBF};
+use crate::error::Error as SbfError;
+use crate::generational::{GenerationalSBF, Rotation};
+use crate::hasher::{stable_encode, SbfHasher};
+use crate::libsbf::{self, CellsMode};
+#[cfg(feature = "mmap")]
+use crate::mapped::MappedSBF;
+use crate::metrics::Metrics;
+use crate::packed::PackedSBF;
+use crate::scalable::ScalableSBF;
+use crate::types::{Area, HashFunction, IndexMode, InputMode, Membership, Salts, CACHE_LINE_BYTES};
+
+/// Returns the filter error wrapped in an I/O error, if any
+fn sbf_error(error: &io::Error) -> Option<&SbfError> {
+    error.get_ref().and_then(|e| e.downcast_ref())
+}
+
+/// Builder of the filters shared by most tests
+fn test_builder<U: Area>() -> SbfBuilder<U> {
+    SBF::<U>::builder()
+        .cells(1000)
+        .hash_number(3)
+        .max_input_size(8)
+        .hash_function(HashFunction::MD5)
+        .area_number(4)
+        .seed(42)
+}
 
 #[test]
 fn test_sbf() -> Result<(), Box<dyn Error>> {
-    let mut sbf = SBF::new(
-        10u8,
+    let mut sbf = SBF::<u8>::new(
+        10,
         2,
         5,
         HashFunction::MD5,
@@ -21,7 +56,7 @@ fn test_sbf() -> Result<(), Box<dyn Error>> {
     }
     assert!(sbf.filter.par_iter().all(|v| *v == 0));
 
-    sbf.insert(b"test".to_vec(), 1)
+    sbf.insert(b"test", 1)
         .expect("Correct insertion of an area");
     #[cfg(feature = "serde_support")]
     {
@@ -31,7 +66,7 @@ fn test_sbf() -> Result<(), Box<dyn Error>> {
     assert!(2 >= count && count > 0);
     let filter = sbf.filter.clone();
 
-    sbf.insert(b"test".to_vec(), 1)
+    sbf.insert(b"test", 1)
         .expect("Correct insertion of an area");
     #[cfg(feature = "serde_support")]
     {
@@ -39,7 +74,7 @@ fn test_sbf() -> Result<(), Box<dyn Error>> {
     }
     assert_eq!(filter, sbf.filter);
 
-    sbf.insert(b"test1".to_vec(), 2)
+    sbf.insert(b"test1", 2)
         .expect("Correct insertion of an area");
     #[cfg(feature = "serde_support")]
     {
@@ -47,7 +82,7 @@ fn test_sbf() -> Result<(), Box<dyn Error>> {
     }
     let filter = sbf.filter.clone();
 
-    sbf.insert(b"test1".to_vec(), 2)
+    sbf.insert(b"test1", 2)
         .expect("Correct insertion of an area");
     #[cfg(feature = "serde_support")]
     {
@@ -83,3 +118,1300 @@ fn test_sbf() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[test]
+fn test_sbf_cells_larger_than_area_type() -> Result<(), Box<dyn Error>> {
+    let mut sbf = SBF::<u8>::new(
+        1_000_000,
+        3,
+        16,
+        HashFunction::MD5,
+        #[cfg(feature = "metrics")]
+        3,
+    )?;
+    assert_eq!(1_000_000, sbf.filter.len());
+
+    let area = |i: u32| 1 + (i % 2) as u8;
+    (0..1000u32).try_for_each(|i| sbf.insert(i.to_le_bytes(), area(i)))?;
+    (0..1000u32).try_for_each(|i| {
+        assert!(*sbf.check(i.to_le_bytes())? >= area(i));
+        Ok::<(), Box<dyn Error>>(())
+    })?;
+
+    Ok(())
+}
+
+#[test]
+fn test_sbf_hash_functions() -> Result<(), Box<dyn Error>> {
+    let hash_functions = [
+        #[cfg(feature = "md5_hash")]
+        HashFunction::MD5,
+        #[cfg(feature = "md4_hash")]
+        HashFunction::MD4,
+        #[cfg(feature = "sha1_hash")]
+        HashFunction::SHA1,
+        #[cfg(feature = "sha256_hash")]
+        HashFunction::SHA256,
+        #[cfg(feature = "blake3_hash")]
+        HashFunction::BLAKE3,
+        #[cfg(feature = "xxh3_hash")]
+        HashFunction::XXH3,
+        #[cfg(feature = "siphash_hash")]
+        HashFunction::SipHash,
+    ];
+
+    for hash_function in hash_functions {
+        assert!(hash_function.digest(b"test").len() >= 8);
+        assert_eq!(
+            hash_function.digest(b"test"),
+            hash_function.digest_parts(&[b"te", b"st"])
+        );
+
+        let mut sbf = SBF::<u8>::new(
+            100,
+            3,
+            8,
+            hash_function,
+            #[cfg(feature = "metrics")]
+            3,
+        )?;
+        sbf.insert(b"test", 2)?;
+        assert_eq!(2, *sbf.check(b"test")?);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sbf_custom_hasher() -> Result<(), Box<dyn Error>> {
+    /// FNV-1a based hasher
+    #[derive(Clone, Copy, Debug)]
+    struct Fnv;
+
+    impl SbfHasher for Fnv {
+        fn digest(&self, buff: &[u8]) -> Vec<u8> {
+            buff.iter()
+                .fold(0xcbf29ce484222325_u64, |h, b| {
+                    (h ^ *b as u64).wrapping_mul(0x100000001b3)
+                })
+                .to_le_bytes()
+                .to_vec()
+        }
+    }
+
+    let mut sbf = SBF::<u8, Fnv>::new(
+        100,
+        3,
+        8,
+        Fnv,
+        #[cfg(feature = "metrics")]
+        3,
+    )?;
+    sbf.insert(b"test", 1)?;
+    assert_eq!(1, *sbf.check(b"test")?);
+
+    Ok(())
+}
+
+#[test]
+fn test_sbf_double_hashing() -> Result<(), Box<dyn Error>> {
+    let mut sbf = SBF::<u8>::new(
+        10_000,
+        7,
+        8,
+        HashFunction::MD5,
+        #[cfg(feature = "metrics")]
+        4,
+    )?
+    .with_index_mode(IndexMode::DoubleHashing)?;
+
+    let indexes = sbf.calc_indexes(b"test")?;
+    assert_eq!(7, indexes.len());
+    assert!(indexes.iter().all(|i| *i < 10_000));
+    assert_eq!(indexes, sbf.calc_indexes(b"test")?);
+
+    (0..100u32).try_for_each(|i| sbf.insert(i.to_le_bytes(), 1 + (i % 3) as u8))?;
+    (0..100u32).try_for_each(|i| {
+        assert_eq!(1 + (i % 3) as u8, *sbf.check(i.to_le_bytes())?);
+        Ok::<(), Box<dyn Error>>(())
+    })?;
+
+    /// Hash function returning a fixed digest and counting the computed digests
+    #[derive(Clone, Debug)]
+    struct Fixed(Vec<u8>, Arc<AtomicUsize>);
+
+    impl SbfHasher for Fixed {
+        fn digest(&self, _buff: &[u8]) -> Vec<u8> {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            self.0.clone()
+        }
+    }
+
+    let digest = [5u64.to_le_bytes(), u64::MAX.to_le_bytes()].concat();
+    for (digest, digests, strides) in [
+        (digest.clone(), 1, [15, 16, 18, 21]),
+        (digest[..8].to_vec(), 2, [5, 6, 8, 11]),
+    ] {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let sbf = SBF::<u8, Fixed>::builder()
+            .cells(100)
+            .hash_number(5)
+            .max_input_size(8)
+            .hash_function(Fixed(digest, counter.clone()))
+            .index_mode(IndexMode::DoubleHashing)
+            .build()?;
+        let indexes = sbf.calc_indexes(b"test")?;
+        assert_eq!(digests, counter.load(Ordering::Relaxed));
+        assert_eq!(5, indexes[0]);
+        // Strides of `h2`, `h2 + 1`, `h2 + 3`, `h2 + 6`, reduced modulo the number of cells
+        let found: Vec<usize> = indexes
+            .windows(2)
+            .map(|w| (w[1] + 100 - w[0]) % 100)
+            .collect();
+        assert_eq!(strides.to_vec(), found);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sbf_long_inputs() -> Result<(), Box<dyn Error>> {
+    let long_a = [b"shared prefix".to_vec(), b"a".repeat(100)].concat();
+    let long_b = [b"shared prefix".to_vec(), b"b".repeat(100)].concat();
+
+    let mut sbf = SBF::<u8>::new(
+        1000,
+        3,
+        8,
+        HashFunction::MD5,
+        #[cfg(feature = "metrics")]
+        3,
+    )?;
+    assert_eq!(Err(SbfError::InputTooLong), sbf.insert(long_a.clone(), 1));
+    assert_eq!(Err(SbfError::InputTooLong), sbf.check(long_b.clone()));
+
+    let mut sbf = SBF::<u8>::new(
+        1000,
+        3,
+        8,
+        HashFunction::MD5,
+        #[cfg(feature = "metrics")]
+        3,
+    )?
+    .with_input_mode(InputMode::Streamed);
+    assert_ne!(sbf.calc_indexes(&long_a)?, sbf.calc_indexes(&long_b)?);
+    sbf.insert(long_a.clone(), 1)?;
+    assert_eq!(1, *sbf.check(long_a)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_sbf_seeded() -> Result<(), Box<dyn Error>> {
+    let new_seeded = |seed| {
+        SBF::<u8>::new_seeded(
+            1000,
+            3,
+            8,
+            HashFunction::MD5,
+            seed,
+            #[cfg(feature = "metrics")]
+            3,
+        )
+    };
+
+    let mut a = new_seeded(42)?;
+    let mut b = new_seeded(42)?;
+    let c = new_seeded(43)?;
+    assert_eq!(a.salts, b.salts);
+    assert_ne!(a.salts, c.salts);
+
+    a.insert(b"test", 1)?;
+    b.insert(b"test", 1)?;
+    assert_eq!(a.filter, b.filter);
+
+    Ok(())
+}
+
+#[test]
+fn test_sbf_derived_salts() -> Result<(), Box<dyn Error>> {
+    let mut sbf = SBF::<u8>::new_with_key(
+        1000,
+        3,
+        64,
+        HashFunction::MD5,
+        [7; 32],
+        #[cfg(feature = "metrics")]
+        3,
+    )?;
+    assert_eq!(3, sbf.salts.len());
+    let salts: Vec<_> = sbf.salts.iter().collect();
+    assert!(salts.iter().all(|salt| salt.len() == 64));
+    assert_ne!(salts[0], salts[1]);
+    assert_eq!(salts[2], sbf.salts.get(2).unwrap());
+    assert_eq!(None, sbf.salts.get(3));
+
+    sbf.insert(b"test", 2)?;
+    assert_eq!(2, *sbf.check(b"test")?);
+
+    #[cfg(feature = "serde_support")]
+    {
+        let serialized = serde_json::to_string(&sbf.salts)?;
+        assert!(serialized.len() < 200);
+        assert_eq!(sbf.salts, serde_json::from_str(&serialized)?);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sbf_builder() -> Result<(), Box<dyn Error>> {
+    let builder = test_builder::<u8>()
+        .max_input_size(16)
+        .area_number(3)
+        .index_mode(IndexMode::DoubleHashing);
+
+    let mut sbf = builder.clone().build()?;
+    assert_eq!(3, sbf.area_number());
+    assert_eq!(sbf.salts, builder.clone().build()?.salts);
+    sbf.insert(b"test", 2)?;
+    assert_eq!(2, *sbf.check(b"test")?);
+
+    assert_eq!(
+        SbfError::ZeroCells,
+        builder.clone().cells(0).build().unwrap_err()
+    );
+    assert_eq!(
+        SbfError::ZeroHashes,
+        builder.clone().hash_number(0).build().unwrap_err()
+    );
+    assert_eq!(
+        SbfError::ZeroInputSize,
+        builder.clone().max_input_size(0).build().unwrap_err()
+    );
+    assert_eq!(
+        SbfError::NotEnoughAreas,
+        builder.clone().area_number(1).build().unwrap_err()
+    );
+    assert_eq!(
+        SbfError::TooManyAreas,
+        builder.clone().area_number(257).build().unwrap_err()
+    );
+    assert!(builder.clone().area_number(256).build().is_ok());
+    assert_eq!(
+        SbfError::MissingHashFunction,
+        SBF::<u8>::builder()
+            .cells(1000)
+            .hash_number(3)
+            .max_input_size(16)
+            .build()
+            .unwrap_err()
+    );
+
+    #[cfg(feature = "metrics")]
+    {
+        let mut sbf = builder.clone().metrics(true).build()?;
+        sbf.insert(b"test", 2)?;
+        assert_eq!(Some(1), sbf.metrics.get_area_members(2));
+    }
+    #[cfg(not(feature = "metrics"))]
+    assert_eq!(
+        SbfError::MetricsUnavailable,
+        builder.metrics(true).build().unwrap_err()
+    );
+
+    // Filters serialized before the number of areas was stored accept every area of `U`
+    #[cfg(feature = "serde_support")]
+    {
+        let mut sbf = test_builder::<u8>().build()?;
+        sbf.insert(b"test", 3)?;
+        let mut json = serde_json::to_value(&sbf)?;
+        let fields = json.as_object_mut().unwrap();
+        for field in ["area_number", "index_mode", "input_mode", "metrics_enabled"] {
+            fields.remove(field);
+        }
+        let baseline: SBF<u8> = serde_json::from_value(json)?;
+        assert_eq!(256, baseline.area_number());
+        assert_eq!(8, PackedSBF::new(baseline.clone())?.bits_per_cell());
+        assert!(baseline.is_compatible(&test_builder::<u8>().area_number(256).build()?));
+
+        let mut bytes = Vec::new();
+        baseline.write_to(&mut bytes)?;
+        let read = SBF::<u8>::read_from(bytes.as_slice())?;
+        assert_eq!(256, read.area_number());
+        assert_eq!(3, *read.check(b"test")?);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sbf_errors() -> Result<(), Box<dyn Error>> {
+    let mut sbf = test_builder::<u8>().cells(100).area_number(3).build()?;
+    assert_eq!(Err(SbfError::ReservedAreaZero), sbf.insert(b"test", 0));
+    assert_eq!(Err(SbfError::AreaOutOfRange), sbf.insert(b"test", 3));
+    assert!(sbf.insert(b"test", 2).is_ok());
+
+    assert_eq!(
+        SbfError::ZeroCells,
+        SBF::<u8>::new(
+            0,
+            3,
+            8,
+            HashFunction::MD5,
+            #[cfg(feature = "metrics")]
+            3
+        )
+        .unwrap_err()
+    );
+    assert_eq!(
+        SbfError::ZeroHashes,
+        SBF::<u8>::new(
+            100,
+            0,
+            8,
+            HashFunction::MD5,
+            #[cfg(feature = "metrics")]
+            3
+        )
+        .unwrap_err()
+    );
+    assert_eq!(
+        SbfError::InvalidParameters,
+        SBF::<u8>::new_optimal(
+            100,
+            1.5,
+            8,
+            HashFunction::MD5,
+            #[cfg(feature = "metrics")]
+            3
+        )
+        .unwrap_err()
+    );
+
+    sbf.salts = Salts::Stored(Vec::new());
+    assert_eq!(Err(SbfError::EmptyHashSet), sbf.check(b"test"));
+
+    /// Hash function with a too short digest
+    #[derive(Clone, Copy, Debug)]
+    struct Short;
+
+    impl SbfHasher for Short {
+        fn digest(&self, buff: &[u8]) -> Vec<u8> {
+            buff.iter().take(4).cloned().collect()
+        }
+    }
+
+    let sbf = SBF::<u8, Short>::builder()
+        .cells(100)
+        .hash_number(3)
+        .max_input_size(8)
+        .hash_function(Short)
+        .build()?;
+    assert_eq!(Err(SbfError::DigestTooShort), sbf.check(b"test"));
+
+    Ok(())
+}
+
+#[test]
+fn test_planner() -> Result<(), Box<dyn Error>> {
+    use crate::metrics::Planner;
+
+    let planner = Planner::new(vec![0, 1000, 500, 100])
+        .max_fpp(0.01)
+        .max_isep(0.001);
+    let plan = planner.plan()?;
+    println!("PLAN: {:?}", plan);
+    assert!(plan.area_prior_fpp.iter().skip(1).all(|p| *p <= 0.01));
+    assert!(plan.area_prior_isep.iter().skip(1).all(|p| *p <= 0.001));
+
+    let smaller = planner.prior_metrics(plan.cells - 1, plan.hash_number);
+    assert!(!planner.is_satisfied(&smaller));
+
+    let mut sbf = test_builder::<u8>()
+        .cells(plan.cells)
+        .hash_number(plan.hash_number)
+        .area_number(plan.area_number)
+        .metrics(cfg!(feature = "metrics"))
+        .build()?;
+    sbf.insert(b"test", 3)?;
+    assert_eq!(3, *sbf.check(b"test")?);
+
+    assert_eq!(
+        SbfError::InvalidParameters,
+        Planner::new(vec![0, 10]).max_fpp(2.0).plan().unwrap_err()
+    );
+    assert_eq!(
+        SbfError::NotEnoughAreas,
+        Planner::new(vec![0]).plan().unwrap_err()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_prior_metrics() {
+    let marked = |m: f64, k: f64, n: f64| (1.0 - (1.0 - 1.0 / m).powf(k * n)).powf(k);
+    let close = |a: f64, b: f64| (a - b).abs() <= 1e-12 * b.abs().max(1e-300);
+
+    let mut metrics = Metrics::new(1000, 3, 4);
+    metrics.area_members = vec![0, 100, 50, 10];
+    metrics.set_prior_area_fpp();
+    // The posterior probabilities are left untouched
+    assert_eq!(vec![-1.0; 4], metrics.area_fpp);
+    let fpp = |n| marked(1000.0, 3.0, n);
+    assert!(close(fpp(10.0), metrics.area_prior_fpp[3]));
+    assert!(close(fpp(60.0) - fpp(10.0), metrics.area_prior_fpp[2]));
+    assert!(close(fpp(160.0) - fpp(60.0), metrics.area_prior_fpp[1]));
+
+    // The members of an area are overwritten with the `k` cells of the higher areas
+    metrics.set_prior_area_isep();
+    assert!(close(fpp(60.0), metrics.area_prior_isep[1]));
+    assert!(close(fpp(10.0), metrics.area_prior_isep[2]));
+    assert_eq!(0.0, metrics.area_prior_isep[3]);
+    let safep = (1.0 - fpp(60.0)).powf(100.0);
+    assert!(close(safep, metrics.area_prior_safep[1]));
+
+    // `k n` larger than `i32::MAX`
+    let mut large = Metrics::new(1 << 40, 20, 3);
+    large.area_members = vec![0, 1 << 30, 1 << 30];
+    large.set_prior_area_fpp();
+    large.set_prior_area_isep();
+    let expected = marked((1u64 << 40) as f64, 20.0, (1u64 << 30) as f64);
+    assert!(expected > 0.0);
+    assert!(close(expected, large.area_prior_fpp[2]));
+    assert!(close(expected, large.area_prior_isep[1]));
+}
+
+#[test]
+fn test_sbf_borrowed_and_hashable_inputs() -> Result<(), Box<dyn Error>> {
+    let mut sbf = test_builder::<u8>().max_input_size(32).build()?;
+
+    let key = String::from("borrowed");
+    sbf.insert(&key, 1)?;
+    assert_eq!(1, *sbf.check(key.as_bytes())?);
+
+    sbf.insert_encoded("string", 2)?;
+    sbf.insert_encoded(&(42u32, -1i64), 3)?;
+    assert_eq!(2, *sbf.check_encoded("string")?);
+    assert_eq!(3, *sbf.check_encoded(&(42u32, -1i64))?);
+
+    assert_eq!(vec![1, 0, 0, 0], stable_encode(&1u32));
+    assert_eq!(stable_encode(&1u64), stable_encode(&1usize));
+    assert_eq!(
+        [vec![2, 0, 0, 0, 0, 0, 0, 0], vec![1, 0, 2, 0]].concat(),
+        stable_encode(&[1u16, 2u16][..])
+    );
+    // Known answer pinning the encoding of every supported type
+    let value = ("ab", -2i16, true, 'é', [7u8], vec![300usize]);
+    assert_eq!(
+        vec![
+            b'a', b'b', 0xff, // str
+            0xfe, 0xff, // i16
+            1,    // bool
+            0xe9, 0, 0, 0, // char
+            1, 0, 0, 0, 0, 0, 0, 0, 7, // [u8; 1]
+            1, 0, 0, 0, 0, 0, 0, 0, 0x2c, 1, 0, 0, 0, 0, 0, 0, // Vec<usize>
+        ],
+        stable_encode(&value)
+    );
+    assert_eq!(stable_encode("ab"), stable_encode(&String::from("ab")));
+
+    Ok(())
+}
+
+#[test]
+fn test_sbf_batch() -> Result<(), Box<dyn Error>> {
+    let builder = test_builder::<u8>().cells(10_000);
+    // More inputs than a single chunk
+    let contents: Vec<(Vec<u8>, u8)> = (0..2 * BATCH_CHUNK as u32 + 1)
+        .map(|i| (i.to_le_bytes().to_vec(), 1 + (i % 3) as u8))
+        .collect();
+
+    let mut sequential = builder.clone().build()?;
+    contents
+        .iter()
+        .try_for_each(|(content, area)| sequential.insert(content, *area))?;
+
+    let mut batch = builder.clone().build()?;
+    batch.insert_batch(contents.clone())?;
+    assert_eq!(sequential.filter, batch.filter);
+
+    let mut par_batch = builder.clone().build()?;
+    par_batch.par_insert_batch(contents.clone())?;
+    assert_eq!(sequential.filter, par_batch.filter);
+
+    let keys: Vec<&Vec<u8>> = contents.iter().map(|(content, _)| content).collect();
+    let areas = batch.check_batch(&keys)?;
+    assert_eq!(contents.len(), areas.len());
+    keys.iter().zip(&areas).try_for_each(|(key, area)| {
+        assert_eq!(*sequential.check(key)?, *area);
+        Ok::<(), Box<dyn Error>>(())
+    })?;
+
+    // The inputs before an invalid one stay inserted, as with sequential insertions
+    let mut failed = builder.build()?;
+    assert_eq!(
+        Err(SbfError::ReservedAreaZero),
+        failed.insert_batch(vec![
+            (b"valid".to_vec(), 1),
+            (b"invalid".to_vec(), 0),
+            (b"skipped".to_vec(), 2)
+        ])
+    );
+    assert_eq!(1, *failed.check(b"valid")?);
+    assert_eq!(0, *failed.check(b"skipped")?);
+    assert_eq!(
+        Err(SbfError::AreaOutOfRange),
+        failed.par_insert_batch(vec![(b"invalid".to_vec(), 4)])
+    );
+    assert_eq!(10_000, failed.filter.len());
+    assert_eq!(1, *failed.check(b"valid")?);
+
+    Ok(())
+}
+
+#[test]
+fn test_sbf_iterators() -> Result<(), Box<dyn Error>> {
+    let builder = test_builder::<u8>().cells(10_000);
+    let pairs: Vec<([u8; 4], u8)> = (0..1000u32)
+        .map(|i| (i.to_le_bytes(), 1 + (i % 3) as u8))
+        .collect();
+
+    let collected = pairs.clone().into_iter().collect_with(builder.clone())?;
+    let par_collected = pairs
+        .clone()
+        .into_par_iter()
+        .collect_with(builder.clone())?;
+    assert_eq!(collected.filter, par_collected.filter);
+
+    let mut extended = builder.clone().build()?;
+    extended.extend(pairs.clone());
+    assert_eq!(collected.filter, extended.filter);
+
+    let mut par_extended = builder.build()?;
+    par_extended.par_extend(pairs.clone());
+    assert_eq!(collected.filter, par_extended.filter);
+
+    assert_eq!(1, *collected.check(pairs[0].0)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_sbf_query() -> Result<(), Box<dyn Error>> {
+    let mut sbf = test_builder::<u8>().build()?;
+
+    assert_eq!(Membership::Absent, sbf.query(b"test")?);
+    sbf.insert(b"test", 1)?;
+    assert_eq!(Membership::Member { area: 1 }, sbf.query(b"test")?);
+
+    // Overwrite one of the cells with a higher area
+    let index = sbf.calc_indexes(b"test")?[0];
+    sbf.filter[index] = 3;
+    assert_eq!(
+        Membership::Ambiguous {
+            candidates: vec![1, 3]
+        },
+        sbf.query(b"test")?
+    );
+    assert_eq!(1, *sbf.check(b"test")?);
+
+    Ok(())
+}
+
+#[test]
+fn test_sbf_explain() -> Result<(), Box<dyn Error>> {
+    let mut sbf = test_builder::<u8>().build()?;
+    sbf.insert(b"test", 1)?;
+    let index = sbf.calc_indexes(b"test")?[0];
+    sbf.filter[index] = 3;
+
+    let explanation = sbf.explain(b"test")?;
+    assert_eq!(3, explanation.probes.len());
+    assert_eq!(index, explanation.probes[0].cell_index);
+    assert_eq!(3, explanation.probes[0].value);
+    let minimum = explanation.minimum_probe().unwrap();
+    assert_ne!(0, minimum.hash_index);
+    assert_eq!(*sbf.check(b"test")?, minimum.value);
+
+    Ok(())
+}
+
+#[test]
+fn test_sbf_merge() -> Result<(), Box<dyn Error>> {
+    let builder = test_builder::<u8>().metrics(cfg!(feature = "metrics"));
+    let inputs: [(&[u8], u8); 4] = [(b"a", 1), (b"b", 2), (b"c", 3), (b"d", 1)];
+
+    let insert = |sbf: &mut SBF<u8>, inputs: &[(&[u8], u8)]| {
+        inputs
+            .iter()
+            .try_for_each(|(content, area)| sbf.insert(content, *area))
+    };
+    let mut full = builder.clone().build()?;
+    insert(&mut full, &inputs)?;
+    let mut left = builder.clone().build()?;
+    insert(&mut left, &inputs[..2])?;
+    let mut right = builder.clone().build()?;
+    insert(&mut right, &inputs[2..])?;
+
+    let merged = (&left | &right)?;
+    assert_eq!(full.filter, merged.filter);
+    #[cfg(feature = "metrics")]
+    {
+        assert_eq!(full.metrics.members, merged.metrics.members);
+        assert_eq!(full.metrics.area_members, merged.metrics.area_members);
+        assert_eq!(full.metrics.area_cells, merged.metrics.area_cells);
+        assert_eq!(full.metrics.collisions, merged.metrics.collisions);
+    }
+
+    left.merge(&right)?;
+    assert_eq!(full.filter, left.filter);
+
+    let other = builder.seed(7).build()?;
+    assert_eq!(Err(SbfError::IncompatibleFilters), full.merge(&other));
+    assert!((full | &other).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_sbf_intersection_difference() -> Result<(), Box<dyn Error>> {
+    let builder = test_builder::<u8>().metrics(cfg!(feature = "metrics"));
+    let mut before = builder.clone().build()?;
+    before.insert(b"a", 1)?;
+    before.insert(b"b", 2)?;
+    let mut after = before.clone();
+    after.insert(b"c", 3)?;
+
+    let intersection = before.intersection(&after)?;
+    assert_eq!(1, *intersection.check(b"a")?);
+    assert_eq!(2, *intersection.check(b"b")?);
+    assert_eq!(0, *intersection.check(b"c")?);
+
+    let added = after.difference(&before)?;
+    assert_eq!(3, *added.check(b"c")?);
+    assert!(before.difference(&after)?.filter.iter().all(|c| *c == 0));
+    #[cfg(feature = "metrics")]
+    assert_eq!(
+        added.filter.iter().filter(|c| **c != 0).count(),
+        added.metrics.area_cells[3]
+    );
+
+    let diff = before.diff(&after)?;
+    assert!(!diff.is_empty());
+    for cell in &diff {
+        assert_eq!(3, cell.right);
+        assert_eq!(before.filter[cell.cell_index], cell.left);
+    }
+    assert_eq!(
+        diff.iter().map(|c| c.cell_index).collect::<Vec<_>>(),
+        (0..1000)
+            .filter(|i| added.filter[*i] != 0)
+            .collect::<Vec<_>>()
+    );
+
+    let other = builder.seed(7).build()?;
+    assert_eq!(
+        Err(SbfError::IncompatibleFilters),
+        before.diff(&other).map(|_| ())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_concurrent_sbf() -> Result<(), Box<dyn Error>> {
+    let builder = test_builder::<u16>()
+        .area_number(5)
+        .metrics(cfg!(feature = "metrics"));
+    let inputs: Vec<(String, u16)> = (0..200u16).map(|i| (i.to_string(), i % 4 + 1)).collect();
+
+    let mut sbf = builder.clone().build()?;
+    for (k, a) in &inputs {
+        sbf.insert(k, *a)?;
+    }
+
+    let concurrent = ConcurrentSBF::new(builder.build()?);
+    inputs
+        .par_iter()
+        .try_for_each(|(k, a)| concurrent.insert(k, *a))?;
+    for (k, _) in &inputs {
+        assert_eq!(*sbf.check(k)?, concurrent.check(k)?);
+    }
+    assert_eq!(Err(SbfError::ReservedAreaZero), concurrent.insert(b"a", 0));
+    assert_eq!(Err(SbfError::AreaOutOfRange), concurrent.insert(b"a", 5));
+
+    #[cfg(feature = "metrics")]
+    {
+        let metrics = concurrent.metrics();
+        assert_eq!(sbf.metrics.members, metrics.members);
+        assert_eq!(sbf.metrics.area_members, metrics.area_members);
+        assert_eq!(sbf.metrics.area_cells, metrics.area_cells);
+        assert_eq!(sbf.metrics.collisions, metrics.collisions);
+    }
+
+    let converted = concurrent.into_sbf();
+    assert_eq!(sbf.filter, converted.filter);
+
+    Ok(())
+}
+
+#[test]
+fn test_counting_sbf() -> Result<(), Box<dyn Error>> {
+    let builder = test_builder::<u8>().metrics(cfg!(feature = "metrics"));
+    let mut sbf = CountingSBF::new(builder.clone().build()?)?;
+
+    sbf.insert(b"zone", 1)?;
+    sbf.insert(b"zone", 3)?;
+    assert_eq!(3, *sbf.check(b"zone")?);
+    let index = sbf.as_sbf().calc_indexes(b"zone")?[0];
+    assert_eq!(1, sbf.get_counter(index, 1)?);
+    assert_eq!(1, sbf.get_counter(index, 3)?);
+
+    // Reassign the zone
+    sbf.remove(b"zone", 3)?;
+    assert_eq!(1, *sbf.check(b"zone")?);
+    assert_eq!(Err(SbfError::NotPresent), sbf.remove(b"zone", 3));
+    sbf.remove(b"zone", 1)?;
+    assert_eq!(0, *sbf.check(b"zone")?);
+    assert!(sbf.as_sbf().filter.iter().all(|c| *c == 0));
+    #[cfg(feature = "metrics")]
+    {
+        assert_eq!(0, sbf.as_sbf().metrics.members);
+        assert_eq!(vec![0; 4], sbf.as_sbf().metrics.area_cells);
+    }
+
+    let mut filled = builder.build()?;
+    filled.insert(b"zone", 1)?;
+    assert_eq!(
+        Err(SbfError::InvalidParameters),
+        CountingSBF::new(filled).map(|_| ())
+    );
+
+    // Filters with many areas only keep the non zero counters
+    let mut wide = CountingSBF::new(test_builder::<u16>().area_number(1000).build()?)?;
+    let area = 999;
+    wide.insert(b"zone", 2)?;
+    wide.insert(b"zone", area)?;
+    assert_eq!(area, *wide.check(b"zone")?);
+    let index = wide.as_sbf().calc_indexes(b"zone")?[0];
+    assert_eq!(1, wide.get_counter(index, area)?);
+    assert_eq!(0, wide.get_counter(index, 3)?);
+    #[cfg(feature = "serde_support")]
+    {
+        wide = serde_json::from_str(&serde_json::to_string(&wide)?)?;
+        assert_eq!(1, wide.get_counter(index, area)?);
+    }
+    wide.remove(b"zone", area)?;
+    assert_eq!(2, *wide.check(b"zone")?);
+    wide.remove(b"zone", 2)?;
+    assert_eq!(0, *wide.check(b"zone")?);
+
+    Ok(())
+}
+
+#[test]
+fn test_scalable_sbf() -> Result<(), Box<dyn Error>> {
+    let builder = test_builder::<u8>().cells(100);
+    let mut sbf = ScalableSBF::new(builder.clone())?;
+    assert_eq!(1, sbf.layers().len());
+
+    let inputs: Vec<(String, u8)> = (0..500u16)
+        .map(|i| (i.to_string(), (i % 3 + 1) as u8))
+        .collect();
+    for (k, a) in &inputs {
+        sbf.insert(k, *a)?;
+    }
+    let layers = sbf.layers();
+    assert!(layers.len() > 2);
+    for (previous, next) in layers.iter().zip(&layers[1..]) {
+        assert_eq!(2 * previous.filter.len(), next.filter.len());
+    }
+    let first_fill = layers[0].filter.iter().filter(|c| **c != 0).count();
+    assert!((50..60).contains(&first_fill));
+
+    // No false negatives, and no lower area than inserted
+    for (k, a) in &inputs {
+        assert!(sbf.check(k)? >= *a);
+    }
+
+    assert_eq!(
+        Err(SbfError::InvalidParameters),
+        ScalableSBF::with_growth(builder, 0.5, 0, 0.9).map(|_| ())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_generational_sbf() -> Result<(), Box<dyn Error>> {
+    let builder = test_builder::<u8>();
+
+    let mut sbf = GenerationalSBF::new(builder.clone(), 2, Rotation::Inserts(2))?;
+    sbf.insert(b"a", 1)?;
+    sbf.insert(b"b", 2)?;
+    sbf.insert(b"c", 3)?;
+    assert_eq!(2, sbf.generations().count());
+    assert_eq!(1, sbf.check(b"a")?);
+    sbf.insert(b"d", 1)?;
+    sbf.insert(b"e", 1)?;
+    // The generation holding `a` and `b` has been dropped
+    assert_eq!(0, sbf.check(b"a")?);
+    assert_eq!(0, sbf.check(b"b")?);
+    assert_eq!(3, sbf.check(b"c")?);
+    assert_eq!(1, sbf.check(b"e")?);
+
+    let interval = Duration::from_millis(20);
+    let mut sbf = GenerationalSBF::new(builder.clone(), 2, Rotation::Interval(interval))?;
+    sbf.insert(b"a", 1)?;
+    assert_eq!(1, sbf.check(b"a")?);
+    std::thread::sleep(2 * interval);
+    // Expired even without a rotation
+    assert_eq!(0, sbf.check(b"a")?);
+    sbf.insert(b"b", 2)?;
+    assert_eq!(0, sbf.check(b"a")?);
+    assert_eq!(2, sbf.check(b"b")?);
+
+    assert_eq!(
+        Err(SbfError::InvalidParameters),
+        GenerationalSBF::new(builder, 2, Rotation::Inserts(0)).map(|_| ())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_binary_format() -> Result<(), Box<dyn Error>> {
+    let mut sbf = test_builder::<u16>()
+        .area_number(300)
+        .input_mode(InputMode::Streamed)
+        .metrics(cfg!(feature = "metrics"))
+        .build()?;
+    sbf.insert(b"a", 1)?;
+    sbf.insert(b"b", 299)?;
+
+    let mut buffer = Vec::new();
+    sbf.write_to(&mut buffer)?;
+    assert_eq!(b"SBF\0", &buffer[..4]);
+    let read = SBF::<u16>::read_from(buffer.as_slice())?;
+    assert_eq!(sbf.filter, read.filter);
+    assert_eq!(sbf.salts, read.salts);
+    assert_eq!(299, *read.check(b"b")?);
+    #[cfg(feature = "metrics")]
+    {
+        assert_eq!(sbf.metrics.members, read.metrics.members);
+        assert_eq!(sbf.metrics.area_members, read.metrics.area_members);
+        assert_eq!(sbf.metrics.area_cells, read.metrics.area_cells);
+    }
+
+    // Derived salts are stored as their key
+    let mut derived = test_builder::<u8>()
+        .max_input_size(1024)
+        .key([7; 32])
+        .build()?;
+    derived.insert(b"a", 1)?;
+    let mut compact = Vec::new();
+    derived.write_to(&mut compact)?;
+    assert!(compact.len() < 1200);
+    assert_eq!(1, *SBF::<u8>::read_from(compact.as_slice())?.check(b"a")?);
+
+    // The header is padded to the cache line size, files of version 1 are not
+    let header_len = compact.len() - 1000 - 4;
+    assert_eq!(0, header_len % CACHE_LINE_BYTES);
+    let unpadded_len = 27 + 1 + 32 + 8 + 8 + 1;
+    let mut version_1 = compact[..unpadded_len].to_vec();
+    version_1[4..6].copy_from_slice(&1u16.to_le_bytes());
+    version_1.extend_from_slice(&compact[header_len..compact.len() - 4]);
+    version_1.extend_from_slice(&crc32fast::hash(&version_1).to_le_bytes());
+    assert_eq!(1, *SBF::<u8>::read_from(version_1.as_slice())?.check(b"a")?);
+
+    // Cell holding an area out of the number of areas
+    let mut out_of_range = compact.clone();
+    out_of_range[19..27].copy_from_slice(&2u64.to_le_bytes());
+    out_of_range[header_len] = 2;
+    let checksum = out_of_range.len() - 4;
+    let crc = crc32fast::hash(&out_of_range[..checksum]);
+    out_of_range[checksum..].copy_from_slice(&crc.to_le_bytes());
+    let error = SBF::<u8>::read_from(out_of_range.as_slice()).unwrap_err();
+    assert_eq!(Some(&SbfError::CorruptData), sbf_error(&error));
+    out_of_range[header_len] = 1;
+    let crc = crc32fast::hash(&out_of_range[..checksum]);
+    out_of_range[checksum..].copy_from_slice(&crc.to_le_bytes());
+    assert!(SBF::<u8>::read_from(out_of_range.as_slice()).is_ok());
+
+    // The cells of the u16 filter do not fit in u8
+    let error = SBF::<u8>::read_from(buffer.as_slice()).unwrap_err();
+    assert_eq!(Some(&SbfError::TooManyAreas), sbf_error(&error));
+
+    // Number of areas not fitting in the cells
+    let mut areas = buffer.clone();
+    areas[19..27].copy_from_slice(&(1u64 << 40).to_le_bytes());
+    let error = SBF::<u16>::read_from(areas.as_slice()).unwrap_err();
+    assert_eq!(Some(&SbfError::CorruptData), sbf_error(&error));
+    // Metrics counters not matching the number of areas
+    #[cfg(feature = "metrics")]
+    {
+        areas[19..27].copy_from_slice(&299u64.to_le_bytes());
+        let error = SBF::<u16>::read_from(areas.as_slice()).unwrap_err();
+        assert_eq!(Some(&SbfError::CorruptData), sbf_error(&error));
+    }
+
+    let last = buffer.len() - 5;
+    buffer[last] ^= 1;
+    let error = SBF::<u16>::read_from(buffer.as_slice()).unwrap_err();
+    assert_eq!(Some(&SbfError::CorruptData), sbf_error(&error));
+
+    buffer[4] = 3;
+    let error = SBF::<u16>::read_from(buffer.as_slice()).unwrap_err();
+    assert_eq!(Some(&SbfError::UnsupportedVersion), sbf_error(&error));
+
+    Ok(())
+}
+
+#[test]
+fn test_libsbf_interop() -> Result<(), Box<dyn Error>> {
+    let salts = Salts::generate(
+        &mut ChaCha20Rng::seed_from_u64(42),
+        3,
+        libsbf::MAX_INPUT_SIZE,
+    );
+    let mut salt_file = Vec::new();
+    libsbf::write_salts(&salts, &mut salt_file)?;
+    assert_eq!(3 * libsbf::MAX_INPUT_SIZE, salt_file.len());
+    let salts = libsbf::read_salts(salt_file.as_slice(), 3)?;
+
+    let hash_function = libsbf::hash_function(5).unwrap();
+    assert_eq!(Some(5), libsbf::hash_family(hash_function));
+    let mut sbf = SBF::<u8>::new_libsbf(10, hash_function, salts.clone(), 4)?;
+    assert_eq!(1024, sbf.filter.len());
+
+    // The index is given by the highest bits of the first 4 bytes of the digest
+    let salt = salts.get(0).unwrap();
+    let xored: Vec<u8> = b"test"
+        .iter()
+        .zip(salt.iter())
+        .map(|(c, s)| c ^ s)
+        .collect();
+    let digest = hash_function.digest(&xored);
+    let expected = u32::from_le_bytes([digest[0], digest[1], digest[2], digest[3]]) >> 22;
+    assert_eq!(expected as usize, sbf.calc_indexes(b"test")?[0]);
+
+    sbf.insert(b"test", 2)?;
+    sbf.insert(b"other", 3)?;
+    let mut cells = Vec::new();
+    sbf.write_libsbf_cells(&mut cells, CellsMode::NonEmpty)?;
+    let mut copy = SBF::<u8>::new_libsbf(10, hash_function, salts.clone(), 4)?;
+    copy.read_libsbf_cells(cells.as_slice())?;
+    assert_eq!(sbf.filter, copy.filter);
+    assert_eq!(2, *copy.check(b"test")?);
+
+    let mut all = Vec::new();
+    sbf.write_libsbf_cells(&mut all, CellsMode::All)?;
+    assert_eq!(
+        1024,
+        all.split(|b| *b == b'\n').filter(|l| !l.is_empty()).count()
+    );
+
+    assert!(copy.read_libsbf_cells(b"1024;1".as_slice()).is_err());
+    assert!(copy.read_libsbf_cells(b"0,4".as_slice()).is_err());
+    assert_eq!(sbf.filter, copy.filter);
+    assert_eq!(
+        Err(SbfError::InvalidParameters),
+        SBF::<u8>::new_libsbf(33, hash_function, salts, 4).map(|_| ())
+    );
+
+    // libSBF-cpp filters must have a power of two number of cells
+    let builder = test_builder::<u8>()
+        .max_input_size(libsbf::MAX_INPUT_SIZE)
+        .hash_function(hash_function)
+        .index_mode(IndexMode::LibSbf);
+    assert_eq!(
+        SbfError::InvalidParameters,
+        builder.clone().build().unwrap_err()
+    );
+    assert!(builder.cells(1024).build().is_ok());
+
+    // Regression answers computed with OpenSSL following the described libSBF-cpp derivation,
+    // not produced by the library, for the inputs `alice`, `bob`, `carol` and `dave` inserted in
+    // the areas 1, 2, 3 and 1
+    let salt_file: Vec<u8> = (0..3usize)
+        .flat_map(|k| (0..libsbf::MAX_INPUT_SIZE).map(move |j| (k * 131 + j * 7 + 1) as u8))
+        .collect();
+    let salts = libsbf::read_salts(salt_file.as_slice(), 3)?;
+    let known_answers: [(u32, [[usize; 3]; 4], &str); 3] = [
+        (
+            1,
+            [
+                [185, 940, 99],
+                [863, 388, 831],
+                [897, 537, 977],
+                [686, 786, 178],
+            ],
+            "99;1 178;1 185;1 388;2 537;3 686;1 786;1 831;2 863;2 897;3 940;1 977;3",
+        ),
+        (
+            4,
+            [
+                [234, 374, 712],
+                [685, 1004, 125],
+                [372, 974, 1009],
+                [416, 616, 0],
+            ],
+            "0;1 125;2 234;1 372;3 374;1 416;1 616;1 685;2 712;1 974;3 1004;2 1009;3",
+        ),
+        (
+            5,
+            [
+                [691, 1, 305],
+                [75, 482, 916],
+                [301, 676, 976],
+                [825, 534, 468],
+            ],
+            "1;1 75;2 301;3 305;1 468;1 482;2 534;1 676;3 691;1 825;1 916;2 976;3",
+        ),
+    ];
+    for (family, indexes, cells) in known_answers {
+        let Some(hash_function) = libsbf::hash_function(family) else {
+            continue;
+        };
+        let mut sbf = SBF::<u8>::new_libsbf(10, hash_function, salts.clone(), 4)?;
+        for ((input, area), indexes) in [("alice", 1), ("bob", 2), ("carol", 3), ("dave", 1)]
+            .into_iter()
+            .zip(indexes)
+        {
+            assert_eq!(indexes.to_vec(), sbf.calc_indexes(input.as_bytes())?);
+            sbf.insert(input, area)?;
+        }
+        let mut written = Vec::new();
+        sbf.write_libsbf_cells(&mut written, CellsMode::NonEmpty)?;
+        assert_eq!(cells.replace(' ', "\n") + "\n", String::from_utf8(written)?);
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_mapped_sbf() -> Result<(), Box<dyn Error>> {
+    let mut sbf = test_builder::<u16>().area_number(300).build()?;
+    sbf.insert(b"a", 1)?;
+    sbf.insert(b"b", 299)?;
+
+    let path = std::env::temp_dir().join(format!("sbf-mapped-{}.bin", std::process::id()));
+    sbf.write_to(std::fs::File::create(&path)?)?;
+    let mapped = MappedSBF::<u16>::open(&path)?;
+    mapped.verify()?;
+    assert_eq!(1000, mapped.cells());
+    assert_eq!(300, mapped.area_number());
+    for content in [b"a", b"b", b"c"] {
+        assert_eq!(*sbf.check(content)?, mapped.check(content)?);
+    }
+    assert_eq!(Err(SbfError::IndexOutOfBounds), mapped.get_cell(1000));
+
+    // The areas of the u16 filter do not fit in u8
+    let error = MappedSBF::<u8>::open(&path).unwrap_err();
+    assert_eq!(Some(&SbfError::TooManyAreas), sbf_error(&error));
+
+    let mut bytes = std::fs::read(&path)?;
+    let last = bytes.len() - 5;
+    bytes[last] ^= 1;
+    std::fs::write(&path, &bytes)?;
+    assert_eq!(
+        Err(SbfError::CorruptData),
+        MappedSBF::<u16>::open(&path)?.verify()
+    );
+    bytes.pop();
+    std::fs::write(&path, &bytes)?;
+    assert!(MappedSBF::<u16>::open(&path).is_err());
+    std::fs::remove_file(&path)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_packed_sbf() -> Result<(), Box<dyn Error>> {
+    for (area_number, bits) in [(4, 2), (6, 3), (17, 5)] {
+        let builder = test_builder::<u8>()
+            .area_number(area_number)
+            .metrics(cfg!(feature = "metrics"));
+        let inputs: Vec<(String, u8)> = (0..300usize)
+            .map(|i| (i.to_string(), (i % (area_number - 1) + 1) as u8))
+            .collect();
+
+        let mut sbf = builder.clone().build()?;
+        sbf.insert(b"before", 1)?;
+        let mut packed = PackedSBF::new(sbf.clone())?;
+        assert_eq!(bits, packed.bits_per_cell());
+        assert!(packed.size_in_bytes() * 8 < 1000 * bits + 64);
+
+        for (k, a) in &inputs {
+            sbf.insert(k, *a)?;
+            packed.insert(k, *a)?;
+        }
+        for (k, _) in &inputs {
+            assert_eq!(*sbf.check(k)?, packed.check(k)?);
+        }
+        assert_eq!(*sbf.check(b"before")?, packed.check(b"before")?);
+        assert_eq!(
+            Err(SbfError::AreaOutOfRange),
+            packed.insert(b"a", area_number as u8)
+        );
+
+        let unpacked = packed.into_sbf();
+        assert_eq!(sbf.filter, unpacked.filter);
+        #[cfg(feature = "metrics")]
+        {
+            assert_eq!(sbf.metrics.members, unpacked.metrics.members);
+            assert_eq!(sbf.metrics.area_cells, unpacked.metrics.area_cells);
+            assert_eq!(sbf.metrics.collisions, unpacked.metrics.collisions);
+        }
+    }
+
+    // Filters built with `new` accept every area of `U`, the areas used must be given
+    let mut sbf = SBF::<u8>::new(
+        1000,
+        3,
+        8,
+        HashFunction::MD5,
+        #[cfg(feature = "metrics")]
+        4,
+    )?;
+    sbf.insert(b"a", 3)?;
+    let area_number = sbf.area_number();
+    assert_eq!(
+        if area_number > 16 { 8 } else { 2 },
+        PackedSBF::new(sbf.clone())?.bits_per_cell()
+    );
+    let mut packed = PackedSBF::with_area_number(sbf.clone(), 4)?;
+    assert_eq!(2, packed.bits_per_cell());
+    assert_eq!(3, packed.check(b"a")?);
+    assert_eq!(Err(SbfError::AreaOutOfRange), packed.insert(b"b", 4));
+    assert_eq!(
+        Err(SbfError::InvalidParameters),
+        PackedSBF::with_area_number(sbf.clone(), 1).map(|_| ())
+    );
+    assert_eq!(
+        Err(SbfError::InvalidParameters),
+        PackedSBF::with_area_number(sbf.clone(), area_number + 1).map(|_| ())
+    );
+    assert_eq!(
+        Err(SbfError::AreaOutOfRange),
+        PackedSBF::with_area_number(sbf, 3).map(|_| ())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_blocked_sbf() -> Result<(), Box<dyn Error>> {
+    let block_cells = SBF::<u16>::block_cells();
+    assert_eq!(32, block_cells);
+    assert_eq!(64, SBF::<u8>::block_cells());
+
+    let mut sbf = test_builder::<u16>()
+        .hash_number(6)
+        .area_number(3)
+        .index_mode(IndexMode::Blocked)
+        .metrics(cfg!(feature = "metrics"))
+        .build()?;
+    let inputs: Vec<String> = (0..100usize).map(|i| i.to_string()).collect();
+    for (i, k) in inputs.iter().enumerate() {
+        let indexes = sbf.calc_indexes(k.as_bytes())?;
+        assert_eq!(6, indexes.len());
+        let block = indexes[0] / block_cells;
+        assert!(indexes.iter().all(|i| i / block_cells == block));
+        // The trailing cells that do not fill a block are not used
+        assert!(indexes
+            .iter()
+            .all(|i| *i < 1000 / block_cells * block_cells));
+        sbf.insert(k, (i % 2 + 1) as u16)?;
+    }
+    for (i, k) in inputs.iter().enumerate() {
+        assert!(*sbf.check(k)? >= (i % 2 + 1) as u16);
+    }
+
+    let mut bytes = Vec::new();
+    sbf.write_to(&mut bytes)?;
+    let read = SBF::<u16>::read_from(bytes.as_slice())?;
+    assert_eq!(sbf.calc_indexes(b"test")?, read.calc_indexes(b"test")?);
+
+    // Each block fills a single cache line
+    assert_eq!(0, sbf.filter.as_ptr() as usize % CACHE_LINE_BYTES);
+    assert_eq!(0, read.filter.as_ptr() as usize % CACHE_LINE_BYTES);
+
+    // The blocked prior metrics match the measured ones
+    let builder = test_builder::<u8>()
+        .cells(4096)
+        .hash_number(4)
+        .area_number(3)
+        .index_mode(IndexMode::Blocked);
+    let mut measured = builder.clone().build()?;
+    for (area, members) in [(1, 400), (2, 200)] {
+        for i in 0..members {
+            measured.insert(format!("{area}-{i}"), area)?;
+        }
+    }
+    let mut metrics = Metrics::new(4096, 4, 3);
+    metrics.block_cells = Some(SBF::<u8>::block_cells());
+    metrics.members = 600;
+    metrics.area_members = vec![0, 400, 200];
+    metrics.set_expected_area_cells();
+    let close = |expected: f64, measured: usize| {
+        (expected - measured as f64).abs() <= 0.1 * measured as f64
+    };
+
+    let mut false_positives = 0;
+    for i in 0..20_000 {
+        if *measured.check(format!("x{i}"))? != 0 {
+            false_positives += 1;
+        }
+    }
+    assert!(close(
+        metrics.get_filter_prior_fpp() * 20_000.0,
+        false_positives
+    ));
+    let area_cells = |area| measured.filter.iter().filter(|c| **c == area).count();
+    assert!(close(metrics.area_expected_cells[1] as f64, area_cells(0)));
+    assert!(close(
+        metrics.area_expected_cells[2] as f64,
+        area_cells(0) + area_cells(1)
+    ));
+    let mut lower = builder.build()?;
+    for i in 0..400 {
+        lower.insert(format!("1-{i}"), 1)?;
+    }
+    let lower_cells = lower.filter.iter().filter(|c| **c == 1).count();
+    assert!(close(
+        metrics.get_expected_area_emersion(1) * lower_cells as f64,
+        area_cells(1)
+    ));
+
+    #[cfg(feature = "metrics")]
+    {
+        use crate::metrics::Planner;
+
+        assert_eq!(Some(block_cells), sbf.metrics.block_cells);
+        let blocked_fpp = sbf.metrics.get_filter_prior_fpp();
+        let mut unblocked = sbf.metrics.clone();
+        unblocked.block_cells = None;
+        let unblocked_fpp = unblocked.get_filter_prior_fpp();
+        assert!(blocked_fpp > unblocked_fpp && blocked_fpp < 1.0);
+
+        // Blocks as large as the filter give the classic formula
+        unblocked.block_cells = Some(1000);
+        assert!((unblocked.get_filter_prior_fpp() - unblocked_fpp).abs() < 1e-9);
+
+        let planner = Planner::new(vec![0, 1000, 100]).max_fpp(0.01);
+        let plan = planner.plan()?;
+        let blocked_plan = planner.blocked(block_cells).plan()?;
+        assert!(blocked_plan.cells > plan.cells);
+        assert!(blocked_plan
+            .area_prior_fpp
+            .iter()
+            .skip(1)
+            .all(|p| *p <= 0.01));
+    }
+
+    Ok(())
+}
@@ -1,17 +1,146 @@
 //! Common types for the SBF data structure
 
+use std::{borrow::Cow, ops};
+
+use num::{Bounded, FromPrimitive, ToPrimitive, Unsigned, Zero};
+
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
 #[cfg(feature = "serde_support")]
 use serde::{Deserialize, Serialize};
 
+/// Unsigned integer type used to store the areas in the cells of a filter
+///
+/// It is implemented for all the types that satisfy its bounds, i.e. the unsigned integer types.
+pub trait Area:
+    'static
+    + Send
+    + Sync
+    + Clone
+    + Copy
+    + Ord
+    + PartialOrd
+    + Eq
+    + Unsigned
+    + Bounded
+    + Zero
+    + FromPrimitive
+    + ToPrimitive
+    + ops::AddAssign
+    + ops::SubAssign
+{
+}
+
+impl<T> Area for T where
+    T: 'static
+        + Send
+        + Sync
+        + Clone
+        + Copy
+        + Ord
+        + PartialOrd
+        + Eq
+        + Unsigned
+        + Bounded
+        + Zero
+        + FromPrimitive
+        + ToPrimitive
+        + ops::AddAssign
+        + ops::SubAssign
+{
+}
+
 /// Salt string type
 ///
 /// We use a `u8` encoding for the hash string.
 pub type Salt = Vec<u8>;
 
+/// Master key used to derive the salts
+pub type SaltKey = [u8; 32];
+
+/// Container of the hash salts of a filter
+///
+/// Salts can either be stored explicitly or derived on the fly from a master key, in which case
+/// only the key needs to be stored.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde_support", serde(untagged))]
+pub enum Salts {
+    /// Explicitly stored salts
+    Stored(Vec<Salt>),
+    /// Salts derived from a master key
+    ///
+    /// The salt of index `i` is the keystream of ChaCha20 keyed with `key` on the stream `i`.
+    Derived {
+        /// Master key
+        key: SaltKey,
+        /// Number of salts
+        hash_number: usize,
+        /// Length of each salt
+        max_input_size: usize,
+    },
+}
+
+impl Salts {
+    /// Draws `hash_number` salts of `max_input_size` bytes from the given RNG
+    ///
+    /// The salts are drawn in order, one salt at a time, so a seeded RNG produces deterministic
+    /// salts.
+    pub fn generate<R: RngCore>(rng: &mut R, hash_number: usize, max_input_size: usize) -> Self {
+        Salts::Stored(
+            (0..hash_number)
+                .map(|_| {
+                    let mut salt = vec![0; max_input_size];
+                    rng.fill_bytes(&mut salt);
+                    salt
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the number of salts
+    pub fn len(&self) -> usize {
+        match self {
+            Salts::Stored(salts) => salts.len(),
+            Salts::Derived { hash_number, .. } => *hash_number,
+        }
+    }
+
+    /// Returns `true` if there are no salts
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the salt of the given index, deriving it if necessary
+    pub fn get(&self, index: usize) -> Option<Cow<'_, Salt>> {
+        match self {
+            Salts::Stored(salts) => salts.get(index).map(Cow::Borrowed),
+            Salts::Derived {
+                key,
+                hash_number,
+                max_input_size,
+            } => (index < *hash_number).then(|| {
+                let mut rng = ChaCha20Rng::from_seed(*key);
+                rng.set_stream(index as u64);
+                let mut salt = vec![0; *max_input_size];
+                rng.fill_bytes(&mut salt);
+                Cow::Owned(salt)
+            }),
+        }
+    }
+
+    /// Iterates over all the salts, deriving them if necessary
+    pub fn iter(&self) -> impl Iterator<Item = Cow<'_, Salt>> {
+        (0..self.len()).filter_map(move |i| self.get(i))
+    }
+}
+
 /// The kind of hashing function that is used by the data structure
 ///
-/// By default only MD5 is enabled, MD4 can be enabled by using the `md4_hash` feature.
-#[derive(Clone, Copy, Debug)]
+/// By default only MD5 is enabled, the other hash functions can be enabled by using the respective
+/// `*_hash` feature.
+/// Custom hash functions can be used by implementing [`SbfHasher`](crate::hasher::SbfHasher).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
 pub enum HashFunction {
     /// MD5 hash function
@@ -20,4 +149,153 @@ pub enum HashFunction {
     /// MD4 hash function
     #[cfg(feature = "md4_hash")]
     MD4,
-}
\ No newline at end of file
+    /// SHA-1 hash function
+    ///
+    /// The default hash function of libSBF-cpp, see [`libsbf`](crate::libsbf).
+    #[cfg(feature = "sha1_hash")]
+    SHA1,
+    /// SHA-256 hash function
+    #[cfg(feature = "sha256_hash")]
+    SHA256,
+    /// BLAKE3 hash function
+    #[cfg(feature = "blake3_hash")]
+    BLAKE3,
+    /// xxHash3 (128 bit) hash function
+    #[cfg(feature = "xxh3_hash")]
+    XXH3,
+    /// SipHash-1-3 (128 bit) hash function with a zero key
+    ///
+    /// The salts already act as keys for each hash, so no additional key is used.
+    #[cfg(feature = "siphash_hash")]
+    SipHash,
+}
+
+/// Strategy used to derive the cell indexes of an input
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum IndexMode {
+    /// One digest for each salt, every digest selects one cell.
+    ///
+    /// This is the behaviour of the original library. The first 8 bytes of each digest are read
+    /// as a little endian `u64`, so the indexes are the same on every platform.
+    #[default]
+    Salted,
+    /// Enhanced double hashing (Kirsch–Mitzenmacher)
+    ///
+    /// Only one digest is computed (two if the digest is shorter than 16 bytes) and all the cell
+    /// indexes are derived from it.
+    DoubleHashing,
+    /// One digest for each salt, the index is read from the first 4 bytes of the digest as
+    /// described by libSBF-cpp.
+    ///
+    /// The number of cells must be a power of two not larger than `2^32`, see
+    /// [`libsbf`](crate::libsbf).
+    LibSbf,
+    /// Blocked layout, all the cells of an input fall in the same block of
+    /// [`CACHE_LINE_BYTES`] bytes.
+    ///
+    /// The first 8 bytes of the digests select the block, the following ones select the cells in
+    /// the block with enhanced double hashing, so a check touches a single block of the filter.
+    /// The cells of filters and of memory-mapped files are aligned to the blocks, so each block
+    /// is a single cache line.
+    /// Any trailing cells that do not fill a whole block are never used.
+    Blocked,
+}
+
+impl IndexMode {
+    /// Returns `true` if a filter with the given number of cells can use the strategy
+    pub(crate) fn supports_cells(self, cells: usize) -> bool {
+        match self {
+            IndexMode::LibSbf => cells.is_power_of_two() && cells as u64 <= 1 << 32,
+            _ => true,
+        }
+    }
+}
+
+/// Size of the blocks of [`IndexMode::Blocked`], the size of a cache line on most CPUs
+pub const CACHE_LINE_BYTES: usize = 64;
+
+/// Strategy used to combine the salt with the input before hashing
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum InputMode {
+    /// The input is XORed with the salt.
+    ///
+    /// This is the behaviour of the original library, inputs longer than the maximum input size
+    /// are rejected with [`Error::InputTooLong`](crate::error::Error::InputTooLong).
+    #[default]
+    Xor,
+    /// The salt and the input are streamed through the hash function one after the other.
+    ///
+    /// Inputs of any length are supported.
+    Streamed,
+    /// The input is XORed with the first bytes of the salt, without padding, as described by
+    /// libSBF-cpp.
+    ///
+    /// Inputs longer than the maximum input size are rejected with
+    /// [`Error::InputTooLong`](crate::error::Error::InputTooLong).
+    LibSbf,
+}
+
+/// Result of a membership query on a filter
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub enum Membership<U> {
+    /// At least one of the cells of the input is empty, the input has not been inserted
+    Absent,
+    /// All the cells of the input hold the same area
+    Member {
+        /// Area of the input
+        area: U,
+    },
+    /// The cells of the input hold different non-zero areas
+    ///
+    /// Some of the cells have been overwritten by inputs of higher areas, so the answer may be an
+    /// inter-set error. The lowest candidate is the area returned by `check`.
+    Ambiguous {
+        /// Distinct areas held by the cells, in ascending order
+        candidates: Vec<U>,
+    },
+}
+
+/// Cell probed by one of the hashes of an input
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct Probe<U> {
+    /// Index of the hash (and of its salt)
+    pub hash_index: usize,
+    /// Index of the probed cell
+    pub cell_index: usize,
+    /// Value stored in the probed cell
+    pub value: U,
+}
+
+/// Cell holding a different value in two compatible filters
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct CellDiff<U> {
+    /// Index of the cell
+    pub cell_index: usize,
+    /// Value stored in the cell of the first filter
+    pub left: U,
+    /// Value stored in the cell of the second filter
+    pub right: U,
+}
+
+/// Detailed explanation of the answer of a filter to a query
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde_support", derive(Serialize, Deserialize))]
+pub struct Explanation<U> {
+    /// Cells probed by each hash, in the order of the hashes
+    pub probes: Vec<Probe<U>>,
+    /// Position in `probes` of the first probe holding the minimum value, i.e. the answer of
+    /// `check`
+    pub minimum: usize,
+}
+
+impl<U: Copy> Explanation<U> {
+    /// Returns the probe that determined the answer of the query
+    pub fn minimum_probe(&self) -> Option<Probe<U>> {
+        self.probes.get(self.minimum).copied()
+    }
+}